@@ -1,28 +1,35 @@
 use std::collections::HashMap;
+use std::fmt::Write as _;
 use std::fs::File;
 use std::os::fd::{AsRawFd};
 use std::os::unix::prelude::PermissionsExt;
 use std::process::{Command, Stdio};
 use std::ptr;
 use std::sync::OnceLock;
-use clap::Subcommand;
+use anyhow::bail;
+use clap::{Subcommand, ValueEnum};
 use colored::Colorize;
 use walkdir::WalkDir;
+use crate::executor::{executor_ref, record};
+use crate::probe::{probe, Op, ProbeOutcome, Width, PAGE_SIZE};
+use crate::scan::{print_ranges, scan};
 
 static BINARIES: OnceLock<HashMap<String, String>> = OnceLock::new();
 
-fn binaries_ref() -> &'static HashMap<String, String> {
+pub fn binaries_ref() -> &'static HashMap<String, String> {
     BINARIES.get_or_init(|| {
         let mut binaries = HashMap::new();
         let dir = ".";
-        for entry in WalkDir::new(dir) {
+        // Build outputs and git objects are not binaries of the workspace.
+        let skipped = |entry: &walkdir::DirEntry| entry.depth() > 0 && matches!(entry.file_name().to_str(), Some("target" | ".git"));
+        for entry in WalkDir::new(dir).into_iter().filter_entry(|entry| !skipped(entry)) {
             let entry = entry.expect("Failed to read dir entry");
             let name = entry.file_name().to_string_lossy().to_string();
             let path = entry.path();
 
             if let Ok(meta) = path.metadata() {
                 let mode = meta.permissions().mode();
-                if mode & 0o111 != 0 {
+                if meta.is_file() && mode & 0o111 != 0 {
                     binaries.insert(name, path.to_string_lossy().to_string());
                 }
             }
@@ -38,43 +45,229 @@ pub enum BinarySub {
         name: String,
         args: Vec<String>,
     },
+    /// read physical memory, one value per line or as a hexdump.
     Read {
-        addr: String,
+        #[clap(value_parser = parse_addr)]
+        addr: u64,
+        /// the width of every access in bits, 8, 16, 32 or 64.
+        #[clap(long, default_value = "64")]
+        width: Width,
+        /// how many values to read.
+        #[clap(long, conflicts_with = "len")]
+        count: Option<u64>,
+        /// how many bytes to read, a multiple of the width.
+        #[clap(long)]
+        len: Option<u64>,
+        /// print a canonical hexdump with ascii instead of one value per line.
+        #[clap(long)]
+        hexdump: bool,
+    },
+    /// read a physical address in a worker that catches faults, and print what happened as json.
+    Probe {
+        #[clap(value_parser = parse_addr)]
+        addr: u64,
+        /// the width of the access in bits, 8, 16, 32 or 64.
+        #[clap(long, default_value = "64")]
+        width: Width,
+    },
+    /// write physical memory in a worker that catches faults, and print what every store did as json.
+    Write {
+        #[clap(value_parser = parse_addr)]
+        addr: u64,
+        /// the value to store, `0x` hex or decimal.
+        #[clap(value_parser = parse_addr)]
+        value: u64,
+        /// the width of every store in bits, 8, 16, 32 or 64.
+        #[clap(long, default_value = "64")]
+        width: Width,
+        /// how many consecutive values to store, it stops at the first one that fails.
+        #[clap(long, conflicts_with = "len")]
+        count: Option<u64>,
+        /// how many bytes to write, a multiple of the width.
+        #[clap(long)]
+        len: Option<u64>,
+        /// how the stored values follow from the value.
+        #[clap(long, value_enum, default_value_t = Pattern::Fixed)]
+        pattern: Pattern,
+    },
+    /// read a physical address range page by page and print which parts are readable, fault or
+    /// cannot be mapped.
+    Scan {
+        #[clap(value_parser = parse_addr)]
+        start: u64,
+        /// the end of the range, exclusive.
+        #[clap(value_parser = parse_addr)]
+        end: u64,
+        /// the distance between probed addresses.
+        #[clap(long, default_value = "4K", value_parser = parse_addr)]
+        step: u64,
+        /// print the ranges as json instead of a table.
+        #[clap(long)]
+        json: bool,
     },
 }
 
+/// The values `tt binary write` stores.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    /// the value at every address.
+    Fixed,
+    /// the value, then the value plus one and so on, wrapping at the width.
+    Increment,
+    /// every address itself, truncated to the width, the value is ignored.
+    Address,
+}
+
+impl Pattern {
+    /// The `index`th value of the pattern, stored at `addr`.
+    fn value(&self, value: u64, index: u64, addr: u64, width: Width) -> u64 {
+        match self {
+            Pattern::Fixed => value,
+            Pattern::Increment => value.wrapping_add(index) & width.mask(),
+            Pattern::Address => addr & width.mask(),
+        }
+    }
+}
+
 pub fn handle_binary_command(sub: &BinarySub) -> anyhow::Result<()> {
     let binaries = binaries_ref();
     match sub {
         BinarySub::List => {
             binaries
                 .iter()
-                .for_each(|(name, path)| println!("{} {path}", format!("{name}").bright_red()));
+                .for_each(|(name, path)| println!("{} {path}", name.bright_red()));
         }
         BinarySub::Exec { name, args } => {
             match binaries.get(name) {
                 Some(path) => {
-                    let mut cmd = Command::new("bash")
-                        .arg(path)
+                    let mut cmd = Command::new("bash");
+                    cmd.arg(path)
                         .args(args)
                         .stdin(Stdio::inherit())
-                        .stdout(Stdio::inherit())
-                        .spawn()?;
-                    cmd.wait()?;
+                        .stdout(Stdio::inherit());
+                    executor_ref().status(&mut cmd)?;
                 }
                 None => {
                     eprintln!("Failed to find binary: {name}")
                 }
             }
         }
-        BinarySub::Read { addr } => read(addr)?,
+        BinarySub::Read { addr, width, count, len, hexdump } => {
+            let count = access_count(*width, *count, *len)?;
+            if !recorded(format!("read {count} values of {} bits at {addr:#x} from /dev/mem", width.bits())) {
+                read(*addr, *width, count, *hexdump)?
+            }
+        }
+        BinarySub::Probe { addr, width } => {
+            if !recorded(format!("probe a read of {} bits at {addr:#x} from /dev/mem", width.bits())) {
+                println!("{}", serde_json::to_string(&probe(*addr, Op::Read(*width))?)?)
+            }
+        }
+        BinarySub::Write { addr, value, width, count, len, pattern } => {
+            let count = access_count(*width, *count, *len)?;
+            let access = format!(
+                "write {count} values of {} bits at {addr:#x} to /dev/mem, {} from {value:#x}",
+                width.bits(),
+                pattern.to_possible_value().unwrap().get_name(),
+            );
+            if !recorded(access) {
+                write(*addr, *value, *width, count, *pattern)?
+            }
+        }
+        BinarySub::Scan { start, end, step, json } => {
+            if recorded(format!("scan {start:#x}..{end:#x} every {step:#x} bytes of /dev/mem")) {
+                return Ok(());
+            }
+            let ranges = scan(*start, *end, *step)?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&ranges)?);
+            } else {
+                print_ranges(&ranges);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Records an access to `/dev/mem` instead of making it in a dry run, returns whether it did.
+fn recorded(access: String) -> bool {
+    if executor_ref().is_dry_run() {
+        record(&access);
+        return true;
+    }
+    false
+}
+
+/// How many accesses of `width` `--count` or `--len` ask for, one if neither is given.
+fn access_count(width: Width, count: Option<u64>, len: Option<u64>) -> anyhow::Result<u64> {
+    let count = match (count, len) {
+        (_, Some(len)) if !len.is_multiple_of(width.bytes()) => {
+            bail!("--len {len} is not a multiple of the width of {} bytes", width.bytes())
+        }
+        (_, Some(len)) => len / width.bytes(),
+        (Some(count), None) => count,
+        (None, None) => 1,
+    };
+    if count == 0 {
+        bail!("Nothing to access, give a positive --count or --len");
+    }
+    Ok(count)
+}
+
+/// Stores `count` values of `pattern` from `addr` on, each in a worker of its own so that a
+/// fault is reported as json like a probe. It stops at the first store that does not succeed.
+fn write(addr: u64, value: u64, width: Width, count: u64, pattern: Pattern) -> anyhow::Result<()> {
+    if pattern == Pattern::Fixed && value > width.mask() {
+        bail!("{value:#x} does not fit in {} bits", width.bits());
+    }
+    for index in 0..count {
+        let addr = addr + index * width.bytes();
+        let result = probe(addr, Op::Write(width, pattern.value(value, index, addr, width)))?;
+        println!("{}", serde_json::to_string(&result)?);
+        if !matches!(result.outcome, ProbeOutcome::Written { .. }) {
+            break;
+        }
     }
     Ok(())
 }
 
-pub fn read(addr: &str) -> anyhow::Result<()> {
-    let offset_str = addr.strip_prefix("0x").expect("A hex number must start with `0x`");
-    let offset = u64::from_str_radix(offset_str.trim(), 16).expect("Failed to parse hex number");
+/// Parses a physical address or a size, `0x` hex or decimal with an optional `K`, `M` or `G`
+/// suffix, like `0x80000000`, `2147483648` or `2G`.
+pub fn parse_addr(addr: &str) -> anyhow::Result<u64> {
+    let addr = addr.trim();
+    let (number, shift) = match addr.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&addr[..i], 10),
+        Some((i, 'M' | 'm')) => (&addr[..i], 20),
+        Some((i, 'G' | 'g')) => (&addr[..i], 30),
+        _ => (addr, 0),
+    };
+    let parsed = match number.strip_prefix("0x").or_else(|| number.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => number.parse::<u64>(),
+    };
+    let number = parsed.map_err(|e| anyhow::anyhow!("Invalid address `{addr}`: {e}"))?;
+    match number.checked_mul(1 << shift) {
+        Some(addr) => Ok(addr),
+        None => bail!("Invalid address `{addr}`: it does not fit in 64 bits"),
+    }
+}
+
+/// Reads `count` values of `width` from physical address `addr` on, with a single access of
+/// exactly that width each, printing one value per line or a hexdump.
+///
+/// The range may span several pages. A fault kills `tt`, see `tt binary probe` to catch it.
+pub fn read(addr: u64, width: Width, count: u64, hexdump: bool) -> anyhow::Result<()> {
+    if !addr.is_multiple_of(width.bytes()) {
+        bail!("{addr:#x} is not aligned to the width of {} bytes", width.bytes());
+    }
+    let Some(len) = count.checked_mul(width.bytes()) else {
+        bail!("{count} values of {} bytes are too many to read", width.bytes());
+    };
+    let Some(end) = addr.checked_add(len).and_then(|end| end.checked_next_multiple_of(PAGE_SIZE)) else {
+        bail!("Reading {len} bytes at {addr:#x} goes past the end of the address space");
+    };
+    let page = addr - addr % PAGE_SIZE;
+    let map_len = end - page;
 
     let mem = File::open("/dev/mem")?;
     let fd = mem.as_raw_fd();
@@ -83,23 +276,99 @@ pub fn read(addr: &str) -> anyhow::Result<()> {
     unsafe {
         memory = libc::mmap(
             ptr::null_mut(),
-            4096,
+            map_len as usize,
             libc::PROT_READ,
             libc::MAP_SHARED,
             fd,
-            offset as libc::off_t,
+            page as libc::off_t,
         )
     }
 
     if memory == libc::MAP_FAILED {
-        eprintln!("Failed to map memory: {addr}");
-        return Ok(());
+        bail!("Failed to map memory: {addr:#x}: {}", std::io::Error::last_os_error());
     }
-    println!("Successfully mapped address: {addr}");
+    println!("Successfully mapped {len} bytes at {addr:#x}");
 
     // This may get a SIGBUS(7).
-    let slice = unsafe { std::slice::from_raw_parts(memory as *mut u64, 64) };
-    println!("The value of `{addr}`: {}", slice[0]);
+    let start = unsafe { (memory as *const u8).add((addr - page) as usize) };
+    let values = (0..count).map(|i| unsafe { width.read(start.add((i * width.bytes()) as usize)) });
+    if hexdump {
+        let bytes = values
+            .flat_map(|value| value.to_le_bytes().into_iter().take(width.bytes() as usize))
+            .collect::<Vec<_>>();
+        print!("{}", hex_dump(addr, &bytes));
+    } else {
+        let digits = width.bytes() as usize * 2;
+        for (i, value) in values.enumerate() {
+            println!("{:#x}: {value:#0w$x}", addr + i as u64 * width.bytes(), w = digits + 2);
+        }
+    }
+    unsafe { libc::munmap(memory, map_len as usize) };
     Ok(())
 }
 
+/// Formats bytes read from `addr` on like `hexdump -C`, with physical addresses.
+fn hex_dump(addr: u64, bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    let mut previous: Option<&[u8]> = None;
+    let mut repeated = false;
+    for (i, line) in bytes.chunks(16).enumerate() {
+        if previous == Some(line) && line.len() == 16 {
+            if !repeated {
+                dump.push_str("*\n");
+                repeated = true;
+            }
+            continue;
+        }
+        previous = Some(line);
+        repeated = false;
+        write!(dump, "{:08x} ", addr + i as u64 * 16).unwrap();
+        for column in 0..16 {
+            if column == 8 {
+                dump.push(' ');
+            }
+            match line.get(column) {
+                Some(byte) => write!(dump, " {byte:02x}").unwrap(),
+                None => dump.push_str("   "),
+            }
+        }
+        let ascii = line
+            .iter()
+            .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+            .collect::<String>();
+        writeln!(dump, "  |{ascii}|").unwrap();
+    }
+    writeln!(dump, "{:08x}", addr + bytes.len() as u64).unwrap();
+    dump
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_addresses() {
+        let cases = [
+            ("0", 0),
+            ("4096", 4096),
+            ("0x1000", 0x1000),
+            ("0X1000", 0x1000),
+            (" 0xFE940000\n", 0xfe94_0000),
+            ("4K", 4 << 10),
+            ("4k", 4 << 10),
+            ("0x10M", 0x10 << 20),
+            ("2G", 2 << 30),
+            ("0xffffffffffffffff", u64::MAX),
+        ];
+        for (addr, expected) in cases {
+            assert_eq!(parse_addr(addr).unwrap(), expected, "{addr}");
+        }
+    }
+
+    #[test]
+    fn reject_invalid_addresses() {
+        for addr in ["", "0x", "K", "-1", "1.5", "0xg", "4T", "realm-pa", "0x10000000000000000", "0x400000000G"] {
+            assert!(parse_addr(addr).is_err(), "{addr}");
+        }
+    }
+}
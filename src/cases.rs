@@ -0,0 +1,132 @@
+use std::time::Duration;
+use crate::expect::{expect_access, Access};
+use crate::fixture::Shared;
+use crate::qemu::QemuType;
+use crate::requirement::Requirement;
+use crate::test::{
+    module_result, pa_from_shared, upload_tt, StageFuture, TestCase, TestContext, TestOutcome, Variant,
+    DEFAULT_SHARED_ADDR,
+};
+
+/// The tests declared in Rust, in the order they are registered, see `tt test new`.
+pub fn registered() -> Vec<TestCase> {
+    vec![
+        TestCase::new(44, "realm-pa-host-read", test_44)
+            .description("Host reads a realm physical address and expects SIGBUS.")
+            .tags(&["realm", "host", "sigbus"])
+            .modules(&["realm_pa_provider"])
+            .shared(Shared::RealmPa)
+            .requires(&[Requirement::Root, Requirement::DevMem])
+            .timeout(Duration::from_mins(1)),
+        TestCase::new(52, "module-52", test_52)
+            .description("Kernel module test 52 reports ok in /proc/tee-tests/52/result.")
+            .tags(&["module"])
+            .modules(&["test_52"])
+            .timeout(Duration::from_mins(1)),
+        TestCase::new(60, "realm-pa-normal-guest-read", test_60)
+            .description("A normal guest reads realm memory shared through ivshmem and expects SIGBUS.")
+            .tags(&["realm", "guest", "sigbus"])
+            .vmm(QemuType::Normal)
+            .modules(&["realm_pa_provider"])
+            .shared(Shared::RealmPa)
+            .timeout(Duration::from_mins(5))
+            .guest(test_60_guest),
+        TestCase::new(62, "realm-pa-normal-guest-read-smp", test_62)
+            .description("A normal guest with one or four cpus reads realm memory shared through ivshmem and expects SIGBUS.")
+            .tags(&["realm", "guest", "sigbus"])
+            .vmm(QemuType::Normal)
+            .modules(&["realm_pa_provider"])
+            .shared(Shared::RealmPa)
+            .timeout(Duration::from_mins(5))
+            .variants(vec![Variant::new("smp-1").smp(1), Variant::new("smp-4").smp(4).memory("2G")])
+            .guest(test_62_guest),
+        TestCase::new(82, "kernel-pa-realm-guest-read", test_82)
+            .description("A realm reads host kernel memory shared through ivshmem and expects SIGBUS.")
+            .tags(&["realm", "guest", "sigbus"])
+            .vmm(QemuType::Confidential)
+            // this address is usually using by kernel.
+            .shared(Shared::Address("0xFE940000"))
+            .guest(test_82_guest),
+        // 83 and its guest stage 831 were `todo!()` in the old index match, they are kept
+        // registered so that the id stays taken, and skip until they are written.
+        TestCase::new(83, "test-83", test_83)
+            .description("Not implemented yet.")
+            .guest(test_83_guest),
+        // `tt test new` inserts new tests above this line.
+    ]
+}
+
+fn test_44(ctx: &mut TestContext) -> StageFuture<'_> {
+    Box::pin(async move {
+        let addr = ctx.shared()?;
+        expect_access(ctx, &addr, Access::SigBus).await
+    })
+}
+
+fn test_52(ctx: &mut TestContext) -> StageFuture<'_> {
+    Box::pin(async move { module_result(ctx, 52, "ok") })
+}
+
+/// The host stage of test 60.
+fn test_60(ctx: &mut TestContext) -> StageFuture<'_> {
+    Box::pin(async move {
+        let port = ctx.vmm().await?;
+        upload_tt(ctx, port).await?;
+
+        ctx.exec_guest(port).await
+    })
+}
+
+/// The guest stage of test 60.
+fn test_60_guest(ctx: &mut TestContext) -> StageFuture<'_> {
+    Box::pin(async move {
+        let pa = pa_from_shared(DEFAULT_SHARED_ADDR)?;
+        expect_access(ctx, &pa, Access::SigBus).await
+    })
+}
+
+/// The host stage of test 62.
+fn test_62(ctx: &mut TestContext) -> StageFuture<'_> {
+    Box::pin(async move {
+        let port = ctx.vmm().await?;
+        upload_tt(ctx, port).await?;
+
+        ctx.exec_guest(port).await
+    })
+}
+
+/// The guest stage of test 62.
+fn test_62_guest(ctx: &mut TestContext) -> StageFuture<'_> {
+    Box::pin(async move {
+        let pa = pa_from_shared(DEFAULT_SHARED_ADDR)?;
+        expect_access(ctx, &pa, Access::SigBus).await
+    })
+}
+
+/// The host stage of test 82.
+fn test_82(ctx: &mut TestContext) -> StageFuture<'_> {
+    Box::pin(async move {
+        let port = ctx.vmm().await?;
+        upload_tt(ctx, port).await?;
+
+        ctx.exec_guest(port).await
+    })
+}
+
+/// The guest stage of test 82.
+fn test_82_guest(ctx: &mut TestContext) -> StageFuture<'_> {
+    Box::pin(async move {
+        let pa = pa_from_shared(DEFAULT_SHARED_ADDR)?;
+        expect_access(ctx, &pa, Access::SigBus).await
+    })
+}
+
+/// The host stage of test 83.
+fn test_83(_: &mut TestContext) -> StageFuture<'_> {
+    Box::pin(async { Ok(TestOutcome::skip("Test 83 is not implemented yet")) })
+}
+
+/// The guest stage of test 83.
+fn test_83_guest(_: &mut TestContext) -> StageFuture<'_> {
+    Box::pin(async { Ok(TestOutcome::skip("Test 83 is not implemented yet")) })
+}
@@ -1,11 +1,13 @@
 use std::fmt::{Display, Formatter};
 use std::path::Path;
+use anyhow::bail;
 use clap::Subcommand;
 use reqwest::Body;
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use crate::executor::executor_ref;
 
 #[derive(Subcommand, Debug, Clone)]
 pub enum ClientSub {
@@ -15,20 +17,30 @@ pub enum ClientSub {
     Exec {
         command: String,
     },
+    /// print a file of the guest OS.
+    Fetch {
+        path: String,
+    },
 }
 
 pub async fn handle_client_command(sub: &ClientSub, port: u16) -> anyhow::Result<()> {
     match sub {
-        ClientSub::Upload { src } => upload(src, port).await?,
+        ClientSub::Upload { src } => println!("{}", upload(src, port).await?),
         ClientSub::Exec { command } => {
             let res = exec(command, port).await?;
             println!("{}", serde_json::to_string_pretty(&res)?);
         }
+        ClientSub::Fetch { path } => print!("{}", fetch(path, port).await?),
     }
     Ok(())
 }
 
-pub async fn upload(src: &str, port: u16) -> anyhow::Result<()> {
+/// Uploads a file to `/test` of the guest OS, returns the response of the server.
+pub async fn upload(src: &str, port: u16) -> anyhow::Result<String> {
+    executor_ref().upload(src, port).await
+}
+
+pub async fn post_upload(src: &str, port: u16) -> anyhow::Result<String> {
     let path = Path::new(src);
     let name = path.file_name().unwrap().to_str().unwrap();
 
@@ -41,8 +53,7 @@ pub async fn upload(src: &str, port: u16) -> anyhow::Result<()> {
         .body(Body::from(buf))
         .send()
         .await?;
-    println!("{}", res.text().await?);
-    Ok(())
+    Ok(res.text().await?)
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -60,6 +71,10 @@ impl Display for ExecRes {
 }
 
 pub async fn exec(command: &str, port: u16) -> anyhow::Result<ExecRes> {
+    executor_ref().exec(command, port).await
+}
+
+pub async fn post_exec(command: &str, port: u16) -> anyhow::Result<ExecRes> {
     let res = reqwest::Client::new()
         .post(format!("http://127.0.0.1:{port}/exec"))
         .body(json!({
@@ -68,4 +83,16 @@ pub async fn exec(command: &str, port: u16) -> anyhow::Result<ExecRes> {
         .send()
         .await?;
     Ok(res.json::<ExecRes>().await?)
-}
\ No newline at end of file
+}
+
+/// Reads a file of the guest OS.
+pub async fn fetch(path: &str, port: u16) -> anyhow::Result<String> {
+    let res = exec(&format!("cat {path}"), port).await?;
+    if let Some(error) = res.error {
+        bail!("Failed to fetch {path}: {error}");
+    }
+    if res.stdout.is_empty() {
+        bail!("Failed to fetch {path}: {}", res.stderr.trim());
+    }
+    Ok(res.stdout)
+}
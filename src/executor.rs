@@ -0,0 +1,132 @@
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Child, Command, ExitStatus, Output};
+use std::sync::OnceLock;
+use colored::Colorize;
+use crate::client::{self, ExecRes};
+
+static EXECUTOR: OnceLock<Executor> = OnceLock::new();
+
+/// What runs processes on the host and commands in the guest OS.
+pub fn executor_ref() -> &'static Executor {
+    EXECUTOR.get_or_init(|| Executor::System)
+}
+
+/// Swaps the executor, only has an effect before it is first used.
+pub fn set_executor(executor: Executor) {
+    let _ = EXECUTOR.set(executor);
+}
+
+/// Every process tt spawns and every request to the guest OS goes through an executor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Executor {
+    /// Executes everything.
+    System,
+    /// Prints what would be executed and executes nothing, for `tt --dry-run`.
+    Recording,
+}
+
+impl Executor {
+    pub fn is_dry_run(&self) -> bool {
+        *self == Executor::Recording
+    }
+
+    /// Runs a command to its end, collecting the output it does not inherit.
+    pub fn output(&self, command: &mut Command) -> io::Result<Output> {
+        match self {
+            Executor::System => command.output(),
+            Executor::Recording => {
+                record(&command_line(command));
+                Ok(empty_output())
+            }
+        }
+    }
+
+    /// Runs a command to its end.
+    pub fn status(&self, command: &mut Command) -> io::Result<ExitStatus> {
+        match self {
+            Executor::System => command.status(),
+            Executor::Recording => {
+                record(&command_line(command));
+                Ok(ExitStatus::from_raw(0))
+            }
+        }
+    }
+
+    /// Starts a long running command like a vmm, `None` if nothing was started.
+    pub fn spawn(&self, command: &mut Command) -> io::Result<Option<Child>> {
+        match self {
+            Executor::System => command.spawn().map(Some),
+            Executor::Recording => {
+                record(&format!("{} &", command_line(command)));
+                Ok(None)
+            }
+        }
+    }
+
+    /// Runs a command to its end without blocking the async runtime.
+    pub async fn output_async(&self, command: &mut tokio::process::Command) -> io::Result<Output> {
+        match self {
+            Executor::System => command.output().await,
+            Executor::Recording => {
+                record(&command_line(command.as_std()));
+                Ok(empty_output())
+            }
+        }
+    }
+
+    /// Uploads a file to `/test` of the guest OS, returns the response of the server.
+    pub async fn upload(&self, src: &str, port: u16) -> anyhow::Result<String> {
+        match self {
+            Executor::System => client::post_upload(src, port).await,
+            Executor::Recording => {
+                record(&format!("upload {src} to the guest OS at port {port}"));
+                Ok(String::new())
+            }
+        }
+    }
+
+    /// Executes a command in the guest OS.
+    pub async fn exec(&self, command: &str, port: u16) -> anyhow::Result<ExecRes> {
+        match self {
+            Executor::System => client::post_exec(command, port).await,
+            Executor::Recording => {
+                record(&format!("exec `{command}` in the guest OS at port {port}"));
+                Ok(ExecRes {
+                    success: "true".to_string(),
+                    stdout: String::new(),
+                    stderr: String::new(),
+                    error: None,
+                })
+            }
+        }
+    }
+}
+
+/// Prints what a dry run would do.
+pub fn record(line: &str) {
+    println!("{} {line}", "[dry-run]".bright_red());
+}
+
+fn empty_output() -> Output {
+    Output {
+        status: ExitStatus::from_raw(0),
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+    }
+}
+
+/// The command as it would be typed in a shell.
+pub fn command_line(command: &Command) -> String {
+    let mut line = vec![quote(&command.get_program().to_string_lossy())];
+    line.extend(command.get_args().map(|arg| quote(&arg.to_string_lossy())));
+    line.join(" ")
+}
+
+fn quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./:=,+@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
@@ -0,0 +1,159 @@
+use std::fmt::{Display, Formatter};
+use std::os::unix::prelude::ExitStatusExt;
+use std::process::Output;
+use std::str::FromStr;
+use tokio::process::Command;
+use crate::binary::parse_addr;
+use crate::executor::executor_ref;
+use crate::probe::{Fault, FaultSignal, Probe, ProbeOutcome};
+use crate::test::{TestContext, TestOutcome};
+
+/// What reading a physical address is expected to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// The read succeeds, with this value if given.
+    Readable(Option<u64>),
+    /// The reader is killed by SIGBUS.
+    SigBus,
+    /// The reader is killed by SIGSEGV.
+    SigSegv,
+    /// `/dev/mem` refuses to map the address.
+    MapRefused,
+}
+
+impl Display for Access {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Access::Readable(Some(value)) => write!(f, "readable with value {value:#x}"),
+            Access::Readable(None) => write!(f, "readable"),
+            Access::SigBus => write!(f, "SIGBUS"),
+            Access::SigSegv => write!(f, "SIGSEGV"),
+            Access::MapRefused => write!(f, "mmap refused"),
+        }
+    }
+}
+
+/// What reading a physical address actually did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Observed {
+    Value(u64),
+    /// The read faulted, the address is the one that was read.
+    Fault(u64, Fault),
+    Signal(i32),
+    MapFailed(String),
+    /// The reader exited in a way none of the above explains.
+    Exited(Option<i32>, String),
+}
+
+impl Display for Observed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Observed::Value(value) => write!(f, "the read succeeded with value {value:#x}"),
+            Observed::Fault(addr, fault) if !fault.in_granule(*addr) => {
+                write!(f, "the read faulted with {fault}, outside the granule of {addr:#x}")
+            }
+            Observed::Fault(_, fault) => write!(f, "the read faulted with {fault}"),
+            Observed::Signal(signal) => write!(f, "the reader was killed by {}", signal_name(*signal)),
+            Observed::MapFailed(reason) => write!(f, "mmap failed: {reason}"),
+            Observed::Exited(code, stderr) => write!(f, "the reader exited with code {code:?}: {stderr}"),
+        }
+    }
+}
+
+impl FromStr for Access {
+    type Err = String;
+
+    /// Parses `readable`, `readable=<value>`, `sigbus`, `sigsegv` or `map-refused`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "readable" => Ok(Access::Readable(None)),
+            "sigbus" => Ok(Access::SigBus),
+            "sigsegv" => Ok(Access::SigSegv),
+            "map-refused" => Ok(Access::MapRefused),
+            s => match s.strip_prefix("readable=") {
+                Some(value) => parse_addr(value)
+                    .map(|value| Access::Readable(Some(value)))
+                    .map_err(|e| format!("Invalid value in `{s}`: {e:#}")),
+                None => Err(format!(
+                    "Unknown access: `{s}`, use readable, readable=<value>, sigbus, sigsegv or map-refused"
+                )),
+            },
+        }
+    }
+}
+
+impl Access {
+    pub fn matches(&self, observed: &Observed) -> bool {
+        match (self, observed) {
+            (Access::Readable(None), Observed::Value(_)) => true,
+            (Access::Readable(Some(expected)), Observed::Value(value)) => expected == value,
+            // A fault outside the granule that was read does not say anything about it.
+            (Access::SigBus, Observed::Fault(addr, fault)) => {
+                fault.signal == FaultSignal::SigBus && fault.in_granule(*addr)
+            }
+            (Access::SigSegv, Observed::Fault(addr, fault)) => {
+                fault.signal == FaultSignal::SigSegv && fault.in_granule(*addr)
+            }
+            (Access::SigBus, Observed::Signal(signal)) => *signal == libc::SIGBUS,
+            (Access::SigSegv, Observed::Signal(signal)) => *signal == libc::SIGSEGV,
+            (Access::MapRefused, Observed::MapFailed(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+fn signal_name(signal: i32) -> String {
+    match signal {
+        libc::SIGBUS => "SIGBUS(7)".to_string(),
+        libc::SIGSEGV => "SIGSEGV(11)".to_string(),
+        _ => format!("signal {signal}"),
+    }
+}
+
+/// Reads `addr` in a child `tt binary probe`, which reports faults it caught as json.
+///
+/// The child is the running `tt` itself, so this works the same on the host and in the guest.
+pub async fn observe_access(addr: &str) -> anyhow::Result<(Observed, Output)> {
+    let addr = addr.trim();
+    let tt = std::env::current_exe()?;
+    let mut command = Command::new(tt);
+    command.args(["binary", "probe", addr]).kill_on_drop(true);
+    let output = executor_ref().output_async(&mut command).await?;
+    Ok((observed(&output), output))
+}
+
+fn observed(output: &Output) -> Observed {
+    let status = output.status;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stderr = stderr.trim();
+
+    if let Some(signal) = status.signal() {
+        return Observed::Signal(signal);
+    }
+    let probe = stdout.lines().find_map(|line| serde_json::from_str::<Probe>(line).ok());
+    match probe.map(|probe| (probe.addr, probe.outcome)) {
+        Some((_, ProbeOutcome::Readable { value } | ProbeOutcome::Written { value })) => Observed::Value(value),
+        Some((addr, ProbeOutcome::Fault(fault))) => Observed::Fault(addr, fault),
+        Some((_, ProbeOutcome::MapFailed { error, .. })) => Observed::MapFailed(error),
+        Some((_, ProbeOutcome::Killed { signal })) => Observed::Signal(signal),
+        None => Observed::Exited(status.code(), stderr.to_string()),
+    }
+}
+
+/// Passes if `observed` is what `expected` describes, otherwise fails describing what happened.
+pub fn check_access(addr: &str, expected: Access, observed: &Observed) -> TestOutcome {
+    if expected.matches(observed) {
+        return TestOutcome::Pass;
+    }
+    TestOutcome::fail(format!("Expected {expected} reading {}, but {observed}", addr.trim()))
+}
+
+/// Reads `addr` and checks it behaves as `expected`, or as the running variant expects if it
+/// says, the output of the reader goes to `ctx`.
+pub async fn expect_access(ctx: &mut TestContext, addr: &str, expected: Access) -> anyhow::Result<TestOutcome> {
+    let expected = ctx.expected(expected);
+    let (observed, output) = observe_access(addr).await?;
+    ctx.push_output(&output);
+    Ok(check_access(addr, expected, &observed))
+}
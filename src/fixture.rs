@@ -0,0 +1,171 @@
+use log::{debug, info, warn};
+use tokio::sync::OwnedMutexGuard;
+use crate::executor::executor_ref;
+use crate::module::{install_module, module_loaded, realm_physical_address, remove_module};
+use crate::qemu::{manager_ref, QemuGuard};
+
+/// The physical memory a test shares with its vmms through ivshmem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shared {
+    /// The realm memory handed out by the `realm_pa_provider` module.
+    RealmPa,
+    /// A fixed physical address.
+    Address(&'static str),
+}
+
+/// Something set up for one test and torn down when it is dropped.
+pub enum Fixture {
+    Module(ModuleFixture),
+    Shared(SharedMemory),
+    Vmm(VmmFixture),
+}
+
+impl Fixture {
+    pub fn describe(&self) -> String {
+        match self {
+            Fixture::Module(module) => format!("module {}", module.name),
+            Fixture::Shared(shared) => format!("shared memory at {}", shared.addr),
+            Fixture::Vmm(vmm) => format!("qemu at port {}", vmm.port),
+        }
+    }
+}
+
+/// The fixtures of a running test.
+///
+/// They are torn down in the reverse order of their setup, when the test finished or when
+/// they are dropped, so also when the test errors or panics.
+#[derive(Default)]
+pub struct Fixtures(Vec<Fixture>);
+
+impl Fixtures {
+    pub fn push(&mut self, fixture: Fixture) {
+        self.0.push(fixture);
+    }
+
+    pub fn teardown(&mut self) {
+        while let Some(fixture) = self.0.pop() {
+            debug!("Tearing down {}", fixture.describe());
+            drop(fixture);
+        }
+    }
+
+    /// The address of the shared memory, if the test set one up.
+    pub fn shared(&self) -> Option<&str> {
+        self.0.iter().find_map(|fixture| match fixture {
+            Fixture::Shared(shared) => Some(shared.addr.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn vmms_mut(&mut self) -> impl Iterator<Item = &mut VmmFixture> {
+        self.0.iter_mut().filter_map(|fixture| match fixture {
+            Fixture::Vmm(vmm) => Some(vmm),
+            _ => None,
+        })
+    }
+}
+
+impl Drop for Fixtures {
+    fn drop(&mut self) {
+        self.teardown();
+    }
+}
+
+/// A kernel module installed for a test and removed once it finished.
+pub struct ModuleFixture {
+    name: String,
+    /// Whether the module was loaded before the test, then it is used as is and left loaded.
+    preloaded: bool,
+    /// Released only after the module was removed, see [`crate::module::lock_module`].
+    _lock: OwnedMutexGuard<()>,
+}
+
+impl ModuleFixture {
+    pub fn install(name: &str, lock: OwnedMutexGuard<()>) -> anyhow::Result<Self> {
+        // insmod fails with EEXIST on a loaded module, like the provider of memory a vmm still
+        // shares, which the test can use all the same.
+        let preloaded = module_loaded(name);
+        if preloaded {
+            info!("Module {name} is loaded already, using it");
+        } else {
+            install_module(name, &[])?;
+        }
+        Ok(Self {
+            name: name.to_string(),
+            preloaded,
+            _lock: lock,
+        })
+    }
+}
+
+impl Drop for ModuleFixture {
+    fn drop(&mut self) {
+        if self.preloaded {
+            return;
+        }
+        if let Err(e) = remove_module(&self.name) {
+            warn!("Failed to remove module {}: {e:#}", self.name);
+        }
+    }
+}
+
+/// The memory a test shares with its vmms.
+pub struct SharedMemory {
+    pub addr: String,
+    /// Whether the memory goes away with the test, like the realm memory of a removed module.
+    released: bool,
+}
+
+impl SharedMemory {
+    pub fn setup(shared: Shared) -> anyhow::Result<Self> {
+        let (addr, released) = match shared {
+            // A dry run installed no provider to ask.
+            Shared::RealmPa if executor_ref().is_dry_run() => {
+                let addr = realm_physical_address().map(|addr| format!("{addr:#x}"));
+                (addr.unwrap_or("$REALM_PA".to_string()), true)
+            }
+            Shared::RealmPa => (format!("{:#x}", realm_physical_address()?), true),
+            Shared::Address(addr) => (addr.to_string(), false),
+        };
+        Ok(Self {
+            addr: addr.trim().to_string(),
+            released,
+        })
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        // No vmm may keep mapping memory that is handed to someone else.
+        if self.released {
+            let stopped = manager_ref().lock().unwrap().stop_sharing(&self.addr);
+            if stopped > 0 {
+                info!("Stopped {stopped} qemu sharing {}", self.addr);
+            }
+        }
+    }
+}
+
+/// A vmm used by a test, stopped with the fixture if the test owns it.
+pub struct VmmFixture {
+    pub port: u16,
+    /// The vmm if the test owns it, otherwise it is shared with later tests.
+    guard: Option<QemuGuard>,
+}
+
+impl VmmFixture {
+    pub fn owned(port: u16, guard: QemuGuard) -> Self {
+        Self { port, guard: Some(guard) }
+    }
+
+    pub fn shared(port: u16) -> Self {
+        Self { port, guard: None }
+    }
+
+    /// Takes a shared vmm away from later tests, so that it is stopped with the fixture.
+    pub fn own(&mut self) {
+        if self.guard.is_none() {
+            self.guard = manager_ref().lock().unwrap().take(self.port);
+        }
+    }
+}
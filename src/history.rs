@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::io::Write as _;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use clap::Subcommand;
+use colored::Colorize;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use crate::executor::executor_ref;
+use crate::qemu::{QemuType, KERNEL_IMAGE};
+use crate::report::{secs, TestResult};
+use crate::test::{parse_duration, tests_ref, TestOutcome};
+
+/// Where the history of runs is kept in the workspace, one json entry per line.
+pub const HISTORY_PATH: &str = ".tt/history.jsonl";
+
+/// The result of a test in one run, as kept in the history.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HistoryEntry {
+    /// Seconds since the unix epoch when the run finished.
+    pub time: u64,
+    pub id: usize,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    pub outcome: TestOutcome,
+    #[serde(rename = "duration_secs", with = "secs")]
+    pub duration: Duration,
+    /// The git revision of the workspace, with `-dirty` if it has uncommitted changes.
+    pub revision: Option<String>,
+    /// The sha256 of the kernel image, for tests in a vmm.
+    pub kernel: Option<String>,
+    pub vmm: Option<QemuType>,
+}
+
+impl HistoryEntry {
+    fn label(&self) -> String {
+        match &self.variant {
+            Some(variant) => format!("{} ({})[{variant}]", self.id, self.name),
+            None => format!("{} ({})", self.id, self.name),
+        }
+    }
+}
+
+/// Appends the results of a run to the history, subtests are not recorded.
+pub fn record(results: &[TestResult]) -> anyhow::Result<()> {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let revision = revision();
+    let kernel = if results.iter().any(|result| result.vmm.is_some()) { kernel_hash() } else { None };
+    let mut content = String::new();
+    for result in results.iter().filter(|result| result.subtest.is_none()) {
+        let entry = HistoryEntry {
+            time,
+            id: result.id,
+            name: result.name.clone(),
+            variant: result.variant.clone(),
+            outcome: result.outcome.clone(),
+            duration: result.duration,
+            revision: revision.clone(),
+            kernel: result.vmm.and(kernel.clone()),
+            vmm: result.vmm,
+        };
+        content.push_str(&serde_json::to_string(&entry)?);
+        content.push('\n');
+    }
+
+    let path = Path::new(HISTORY_PATH);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(content.as_bytes())?;
+    info!("Run recorded in {HISTORY_PATH}");
+    Ok(())
+}
+
+/// All entries of the history, oldest first.
+pub fn load() -> anyhow::Result<Vec<HistoryEntry>> {
+    let content = match std::fs::read_to_string(HISTORY_PATH) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(anyhow::anyhow!("Failed to read {HISTORY_PATH}: {e}")),
+    };
+    let mut entries = Vec::new();
+    for (index, line) in content.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        match serde_json::from_str::<HistoryEntry>(line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => warn!("Ignoring line {} of {HISTORY_PATH}: {e}", index + 1),
+        }
+    }
+    entries.sort_by_key(|entry| entry.time);
+    Ok(entries)
+}
+
+fn revision() -> Option<String> {
+    let git = |args: &[&str]| {
+        let output = executor_ref().output(Command::new("git").args(args)).ok()?;
+        output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    let revision = git(&["rev-parse", "--short=12", "HEAD"])?;
+    let dirty = git(&["status", "--porcelain", "--untracked-files=no"]).is_some_and(|status| !status.is_empty());
+    Some(if dirty { format!("{revision}-dirty") } else { revision })
+}
+
+fn kernel_hash() -> Option<String> {
+    let output = executor_ref().output(Command::new("sha256sum").arg(KERNEL_IMAGE)).ok()?;
+    if !output.status.success() {
+        warn!("Failed to hash {KERNEL_IMAGE}: {}", String::from_utf8_lossy(&output.stderr).trim());
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout).split_whitespace().next().map(str::to_string)
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum HistorySub {
+    /// the recorded runs of a test and its pass rate.
+    Show {
+        /// the id or name of the test.
+        test: String,
+        /// only consider runs since a date like `2026-10-01` or a duration ago like `7d`.
+        #[clap(long)]
+        since: Option<String>,
+        /// show at most this many runs of the test.
+        #[clap(long, default_value_t = 30)]
+        limit: usize,
+    },
+    /// list tests that passed and fail now.
+    Regressions {
+        /// only consider runs since a date like `2026-10-01` or a duration ago like `7d`.
+        #[clap(long)]
+        since: Option<String>,
+    },
+}
+
+pub fn handle_history_command(sub: &HistorySub) -> anyhow::Result<()> {
+    match sub {
+        HistorySub::Show { test, since, limit } => {
+            let since = since.as_deref().map(parse_since).transpose()?.unwrap_or(0);
+            let entries = load()?;
+            let id = match test.parse::<usize>() {
+                Ok(id) => id,
+                Err(_) => match tests_ref().iter().find(|t| t.name == *test) {
+                    Some(t) => t.id,
+                    None => anyhow::bail!("Unknown test: {test}, see `tt test list`"),
+                },
+            };
+            let entries = entries.iter().filter(|entry| entry.id == id && entry.time >= since).collect::<Vec<_>>();
+            if entries.is_empty() {
+                println!("No run of test {test} recorded in {HISTORY_PATH}");
+                return Ok(());
+            }
+            print_trend(&entries[entries.len().saturating_sub(*limit)..]);
+        }
+        HistorySub::Regressions { since } => {
+            let since = since.as_deref().map(parse_since).transpose()?.unwrap_or(0);
+            print_regressions(&load()?, since);
+        }
+    }
+    Ok(())
+}
+
+/// Prints the runs of a test, one line each, followed by its pass rate.
+fn print_trend(entries: &[&HistoryEntry]) {
+    for entry in entries {
+        let line = format!(
+            "{} {:<12} {:>8.1}s {:<18} {:<12} {:<12} {}",
+            format_time(entry.time),
+            entry.variant.as_deref().unwrap_or("-"),
+            entry.duration.as_secs_f64(),
+            entry.revision.as_deref().unwrap_or("-"),
+            entry.kernel.as_deref().map(|hash| &hash[..hash.len().min(12)]).unwrap_or("-"),
+            entry.vmm.map(|typ| format!("{typ:?}")).unwrap_or("-".to_string()),
+            entry.outcome,
+        );
+        match entry.outcome {
+            TestOutcome::Pass => println!("{}", line.green()),
+            TestOutcome::Skip(_) => println!("{}", line.yellow()),
+            _ => println!("{}", line.red()),
+        }
+    }
+    let ran = entries.iter().filter(|entry| !matches!(entry.outcome, TestOutcome::Skip(_))).count();
+    let passed = entries.iter().filter(|entry| entry.outcome == TestOutcome::Pass).count();
+    let trend = entries
+        .iter()
+        .map(|entry| match entry.outcome {
+            TestOutcome::Pass => '.',
+            TestOutcome::Fail(_) => 'F',
+            TestOutcome::Skip(_) => 's',
+            TestOutcome::Error(_) => 'E',
+            TestOutcome::Timeout(_) => 'T',
+        })
+        .collect::<String>();
+    println!("{} {trend}", "Trend:".bright_red());
+    if ran > 0 {
+        println!("{passed} of {ran} runs passed ({:.1}%)", passed as f64 * 100.0 / ran as f64);
+    }
+}
+
+/// Prints the tests that passed at `since`, or in their first run after it, and fail now.
+fn print_regressions(entries: &[HistoryEntry], since: u64) {
+    let mut by_case = HashMap::<(usize, Option<&str>), Vec<&HistoryEntry>>::new();
+    let mut order = Vec::new();
+    for entry in entries.iter().filter(|entry| !matches!(entry.outcome, TestOutcome::Skip(_))) {
+        let key = (entry.id, entry.variant.as_deref());
+        if !by_case.contains_key(&key) {
+            order.push(key);
+        }
+        by_case.entry(key).or_default().push(entry);
+    }
+
+    let mut regressions = Vec::new();
+    for key in order {
+        let runs = &by_case[&key];
+        let baseline = runs.iter().rposition(|entry| entry.time < since).unwrap_or(0);
+        let latest = runs[runs.len() - 1];
+        if latest.time < since || runs[baseline].outcome != TestOutcome::Pass || !latest.outcome.is_broken() {
+            continue;
+        }
+        let last_pass = runs.iter().rposition(|entry| entry.outcome == TestOutcome::Pass).unwrap();
+        regressions.push((runs[last_pass], runs[last_pass + 1], latest));
+    }
+
+    println!("{} ({}):", "Regressions".bright_red(), regressions.len());
+    for (pass, first_failure, latest) in regressions {
+        println!(
+            "  {}: passed {} at {}, broken since {} at {}: {}",
+            latest.label(),
+            format_time(pass.time),
+            pass.revision.as_deref().unwrap_or("-"),
+            format_time(first_failure.time),
+            first_failure.revision.as_deref().unwrap_or("-"),
+            latest.outcome,
+        );
+    }
+}
+
+/// Parses `YYYY-MM-DD` in UTC or a duration before now like `7d`, into seconds since the epoch.
+fn parse_since(s: &str) -> anyhow::Result<u64> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    if let Ok(ago) = parse_duration(s) {
+        return Ok(now.saturating_sub(ago.as_secs()));
+    }
+    let parts = s.trim().split('-').map(str::parse::<u64>).collect::<Result<Vec<_>, _>>();
+    match parts.as_deref() {
+        Ok([year, month @ 1..=12, day @ 1..=31]) if *year >= 1970 => Ok(days_from_civil(*year, *month, *day) * 86400),
+        _ => anyhow::bail!("Invalid date: `{s}`, use YYYY-MM-DD or a duration like `7d`"),
+    }
+}
+
+/// Days since the epoch of a date of the proleptic Gregorian calendar.
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// `YYYY-MM-DD HH:MM` in UTC.
+fn format_time(time: u64) -> String {
+    let days = time / 86400 + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02} {:02}:{:02}", time % 86400 / 3600, time % 3600 / 60)
+}
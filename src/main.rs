@@ -2,15 +2,33 @@ mod module;
 mod script;
 mod test;
 mod binary;
+mod cases;
 mod client;
+mod executor;
+mod expect;
+mod fixture;
+mod history;
+mod manifest;
+mod module_result;
+mod probe;
 mod qemu;
+mod report;
+mod requirement;
+mod scaffold;
+mod scan;
+mod soak;
+mod watch;
 
+use std::process::ExitCode;
 use clap::{Parser, Subcommand};
 use log::LevelFilter;
 use crate::binary::{handle_binary_command, BinarySub};
 use crate::client::{handle_client_command, ClientSub};
+use crate::executor::{set_executor, Executor};
+use crate::history::{handle_history_command, HistorySub};
 use crate::module::{handle_module_command, ModuleSub};
 use crate::qemu::{handle_qemu_command, QemuSub};
+use crate::report::{handle_report_command, ReportSub};
 use crate::script::{handle_script_command, ScriptSub};
 use crate::test::{handle_test_command, TestSub};
 
@@ -19,6 +37,9 @@ use crate::test::{handle_test_command, TestSub};
 pub struct Args {
     #[clap(subcommand)]
     sub: Subcommands,
+    /// print the commands tt would execute instead of executing them.
+    #[clap(long, global = true)]
+    dry_run: bool,
 }
 
 #[derive(Subcommand, Clone, Debug)]
@@ -58,28 +79,43 @@ enum Subcommands {
         #[clap(subcommand)]
         sub: QemuSub,
     },
+    /// comparison of test reports.
+    Report {
+        #[clap(subcommand)]
+        sub: ReportSub,
+    },
+    /// the recorded runs of tests.
+    History {
+        #[clap(subcommand)]
+        sub: HistorySub,
+    },
 }
 
-async fn handle_command(sub: &Subcommands) -> anyhow::Result<()> {
+async fn handle_command(sub: &Subcommands) -> anyhow::Result<ExitCode> {
     match sub {
-        Subcommands::Module { sub } => handle_module_command(sub),
-        Subcommands::Test { sub } => handle_test_command(sub).await,
-        Subcommands::Script { sub } => handle_script_command(sub),
-        Subcommands::Binary { sub } => handle_binary_command(sub),
-        Subcommands::Client { sub, port } => handle_client_command(sub, *port).await,
+        Subcommands::Module { sub } => handle_module_command(sub)?,
+        Subcommands::Test { sub } => return handle_test_command(sub).await,
+        Subcommands::Script { sub } => handle_script_command(sub)?,
+        Subcommands::Binary { sub } => handle_binary_command(sub)?,
+        Subcommands::Client { sub, port } => handle_client_command(sub, *port).await?,
         Subcommands::Init { .. } => {
-            handle_script_command(&ScriptSub::Exec { name: "start-another-shell".to_string() })
+            handle_script_command(&ScriptSub::Exec { name: "start-another-shell".to_string() })?
         }
-        Subcommands::Qemu { sub } => handle_qemu_command(sub),
+        Subcommands::Qemu { sub } => handle_qemu_command(sub)?,
+        Subcommands::Report { sub } => return handle_report_command(sub),
+        Subcommands::History { sub } => handle_history_command(sub)?,
     }
+    Ok(ExitCode::SUCCESS)
 }
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> anyhow::Result<ExitCode> {
     env_logger::builder()
         .filter_level(LevelFilter::Info)
         .init();
     let args = Args::parse();
-    handle_command(&args.sub).await?;
-    Ok(())
+    if args.dry_run {
+        set_executor(Executor::Recording);
+    }
+    handle_command(&args.sub).await
 }
@@ -0,0 +1,286 @@
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use anyhow::bail;
+use log::warn;
+use serde::Deserialize;
+use walkdir::WalkDir;
+use crate::binary::parse_addr;
+use crate::client::upload;
+use crate::expect::{expect_access, Access};
+use crate::fixture::Shared;
+use crate::qemu::QemuType;
+use crate::requirement::Requirement;
+use crate::test::{
+    module_result, pa_from_shared, parse_duration, upload_tt, StageFuture, TestCase, TestContext, Variant,
+    DEFAULT_SHARED_ADDR,
+};
+
+/// The suffix of manifest files discovered in the workspace.
+const MANIFEST_SUFFIX: &str = ".test.json";
+
+static MANIFEST_PATHS: OnceLock<(Vec<PathBuf>, bool)> = OnceLock::new();
+
+/// Sets the manifests loaded besides the discovered ones, and whether to discover manifests at
+/// all. Only has an effect before the registry is first used.
+pub fn configure_manifests(extra: &[PathBuf], discover: bool) {
+    let _ = MANIFEST_PATHS.set((extra.to_vec(), discover));
+}
+
+/// A test declared in a `<name>.test.json` file instead of Rust code.
+///
+/// ```json
+/// {
+///     "id": 61,
+///     "name": "realm-pa-realm-guest-read",
+///     "tags": ["realm", "guest"],
+///     "modules": ["realm_pa_provider"],
+///     "vmm": "confidential",
+///     "shared": "realm-pa",
+///     "probe": "read",
+///     "expected": "sigbus"
+/// }
+/// ```
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub id: usize,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Kernel modules installed on the host before the test.
+    #[serde(default)]
+    pub modules: Vec<String>,
+    /// The vmm the probe runs in, the probe runs on the host if absent.
+    pub vmm: Option<QemuType>,
+    /// `realm-pa` for the address of the realm PA provider, or a physical address.
+    pub shared: Option<String>,
+    pub probe: Probe,
+    /// For `read`, the expected access, see [`Access`]. For `module-result`, the expected
+    /// content of a `/proc/tee-tests/<id>/result` without subtests.
+    pub expected: String,
+    /// e.g. `90s` or `5m`.
+    pub timeout: Option<String>,
+    /// Requirements besides the implied ones, like `root` or `devmem`, see [`Requirement`].
+    #[serde(default)]
+    pub requires: Vec<String>,
+    /// Configurations the test runs in, each reported as a case of its own.
+    #[serde(default)]
+    pub variants: Vec<ManifestVariant>,
+    #[serde(skip)]
+    pub path: PathBuf,
+}
+
+/// A variant of a manifest test, see [`Variant`], anything absent is taken from the test.
+///
+/// ```json
+/// { "name": "normal", "vmm": "normal", "smp": 4, "memory": "2G", "expected": "readable" }
+/// ```
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestVariant {
+    pub name: String,
+    pub vmm: Option<QemuType>,
+    pub shared: Option<String>,
+    pub smp: Option<u32>,
+    pub memory: Option<String>,
+    /// The expected access of a `read` probe.
+    pub expected: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Probe {
+    /// Reads the shared address, on the host or in the guest.
+    Read,
+    /// Checks `/proc/tee-tests/<id>/result`, a result without subtests is compared with the
+    /// expected one.
+    ModuleResult,
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let mut manifest = serde_json::from_str::<Manifest>(&content)?;
+        manifest.path = path.to_path_buf();
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if let Some(timeout) = &self.timeout {
+            parse_duration(timeout).map_err(anyhow::Error::msg)?;
+        }
+        let variants = self.variants.iter().filter_map(|variant| variant.shared.as_deref());
+        for shared in self.shared.as_deref().into_iter().chain(variants) {
+            parse_shared(shared)?;
+        }
+        self.requirements()?;
+        let mut names = Vec::new();
+        for variant in &self.variants {
+            // The name is part of the command and the result path of the guest stage.
+            let kebab = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-';
+            if variant.name.is_empty() || variant.name.starts_with('-') || !variant.name.chars().all(kebab) {
+                bail!("A variant name must be kebab-case, like `smp-4`, got `{}`", variant.name);
+            }
+            if names.contains(&&variant.name) {
+                bail!("The variant {} is declared twice", variant.name);
+            }
+            names.push(&variant.name);
+        }
+        match self.probe {
+            Probe::Read => {
+                self.access()?;
+                for variant in &self.variants {
+                    if let Some(expected) = &variant.expected {
+                        expected.parse::<Access>().map_err(anyhow::Error::msg)?;
+                    }
+                }
+                let unshared = self.variants.iter().any(|variant| variant.shared.is_none());
+                if self.shared.is_none() && (self.variants.is_empty() || unshared) {
+                    bail!("A `read` probe needs a `shared` address");
+                }
+            }
+            Probe::ModuleResult => {
+                if self.vmm.is_some() || self.variants.iter().any(|variant| variant.vmm.is_some()) {
+                    bail!("A `module-result` probe runs on the host, it cannot have a `vmm`");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn access(&self) -> anyhow::Result<Access> {
+        self.expected.parse::<Access>().map_err(anyhow::Error::msg)
+    }
+
+    fn requirements(&self) -> anyhow::Result<Vec<Requirement>> {
+        let requirements = self.requires.iter().map(|requirement| requirement.parse::<Requirement>());
+        requirements.collect::<Result<_, _>>().map_err(anyhow::Error::msg)
+    }
+
+    /// The file name of the manifest, which is also its name in `/test` of the guest OS.
+    pub fn file_name(&self) -> String {
+        self.path.file_name().unwrap_or_default().to_string_lossy().to_string()
+    }
+
+    /// Turns the manifest into a registered test, the manifest lives as long as the registry.
+    pub fn into_test(self) -> TestCase {
+        let tags = self.tags.iter().cloned().map(leak).collect::<Vec<_>>();
+        let modules = self.modules.iter().cloned().map(leak).collect::<Vec<_>>();
+
+        let mut test = TestCase::new(self.id, leak(self.name.clone()), manifest_host)
+            .description(leak(self.description.clone()))
+            .tags(Box::leak(tags.into_boxed_slice()))
+            .modules(Box::leak(modules.into_boxed_slice()))
+            .requires(Box::leak(self.requirements().unwrap_or_default().into_boxed_slice()));
+        if let Some(timeout) = self.timeout.as_deref().and_then(|t| parse_duration(t).ok()) {
+            test = test.timeout(timeout);
+        }
+        if let Some(shared) = self.shared.as_deref().and_then(|s| parse_shared(s).ok()) {
+            test = test.shared(shared);
+        }
+        if let Some(typ) = self.vmm {
+            test = test.vmm(typ);
+        }
+        if self.vmm.is_some() || self.variants.iter().any(|variant| variant.vmm.is_some()) {
+            test = test.guest(manifest_guest);
+        }
+        let variants = self.variants.iter().map(ManifestVariant::to_variant).collect();
+        test.variants(variants).manifest(self)
+    }
+}
+
+impl ManifestVariant {
+    fn to_variant(&self) -> Variant {
+        let mut variant = Variant::new(leak(self.name.clone()));
+        if let Some(typ) = self.vmm {
+            variant = variant.vmm(typ);
+        }
+        if let Some(shared) = self.shared.as_deref().and_then(|s| parse_shared(s).ok()) {
+            variant = variant.shared(shared);
+        }
+        if let Some(smp) = self.smp {
+            variant = variant.smp(smp);
+        }
+        if let Some(memory) = &self.memory {
+            variant = variant.memory(leak(memory.clone()));
+        }
+        if let Some(expected) = self.expected.as_deref().and_then(|e| e.parse::<Access>().ok()) {
+            variant = variant.expected(expected);
+        }
+        variant
+    }
+}
+
+/// Manifests live as long as the registry.
+fn leak(s: String) -> &'static str {
+    Box::leak(s.into_boxed_str())
+}
+
+/// Parses `realm-pa` or a physical address, see [`parse_addr`].
+fn parse_shared(shared: &str) -> anyhow::Result<Shared> {
+    match shared {
+        "realm-pa" => Ok(Shared::RealmPa),
+        addr => match parse_addr(addr) {
+            Ok(addr) => Ok(Shared::Address(leak(format!("{addr:#x}")))),
+            Err(e) => bail!("`shared` must be `realm-pa` or a physical address: {e:#}"),
+        },
+    }
+}
+
+/// Loads the manifests found in the workspace and the configured ones.
+pub fn manifests() -> Vec<Manifest> {
+    let (extra, discover) = MANIFEST_PATHS.get().cloned().unwrap_or((Vec::new(), true));
+    let mut paths = extra;
+    if discover {
+        for entry in WalkDir::new(".") {
+            let entry = entry.expect("Failed to read dir entry");
+            if entry.file_name().to_string_lossy().ends_with(MANIFEST_SUFFIX) {
+                paths.push(entry.path().to_path_buf());
+            }
+        }
+        paths.sort();
+    }
+
+    let mut manifests = Vec::new();
+    for path in paths {
+        match Manifest::load(&path) {
+            Ok(manifest) => manifests.push(manifest),
+            Err(e) => warn!("Ignoring test manifest {}: {e:#}", path.display()),
+        }
+    }
+    manifests
+}
+
+fn manifest_of(ctx: &TestContext) -> &'static Manifest {
+    ctx.test.manifest.as_ref().expect("A manifest stage of a test without manifest")
+}
+
+fn manifest_host(ctx: &mut TestContext) -> StageFuture<'_> {
+    Box::pin(async move {
+        let manifest = manifest_of(ctx);
+        match (manifest.probe, ctx.case().vmm()) {
+            (Probe::ModuleResult, _) => module_result(ctx, manifest.id, &manifest.expected),
+            (Probe::Read, None) => {
+                let addr = ctx.shared()?;
+                expect_access(ctx, &addr, manifest.access()?).await
+            }
+            (Probe::Read, Some(_)) => {
+                let port = ctx.vmm().await?;
+                upload_tt(ctx, port).await?;
+                upload(&manifest.path.to_string_lossy(), port).await?;
+                ctx.exec_guest(port).await
+            }
+        }
+    })
+}
+
+fn manifest_guest(ctx: &mut TestContext) -> StageFuture<'_> {
+    Box::pin(async move {
+        let manifest = manifest_of(ctx);
+        let pa = pa_from_shared(DEFAULT_SHARED_ADDR)?;
+        expect_access(ctx, &pa, manifest.access()?).await
+    })
+}
@@ -1,16 +1,23 @@
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
-use std::path::PathBuf;
+use std::path::Path;
 use std::process::{Command, Stdio};
-use std::sync::OnceLock;
+use std::sync::{Arc, Mutex, OnceLock};
+use anyhow::bail;
 use clap::Subcommand;
 use colored::Colorize;
 use walkdir::WalkDir;
+use crate::binary::parse_addr;
+use crate::executor::executor_ref;
+
+/// The interface of the `realm_pa_provider` module.
+pub const REALM_PA_INTERFACE: &str = "/proc/interface/get_realm_pa";
 
 static MODULES: OnceLock<HashMap<String, String>> = OnceLock::new();
+static MODULE_LOCKS: OnceLock<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>> = OnceLock::new();
 
-fn modules_ref() -> &'static HashMap<String, String> {
+pub fn modules_ref() -> &'static HashMap<String, String> {
     MODULES.get_or_init(|| {
         let mut modules = HashMap::new();
         let dir = ".";
@@ -49,43 +56,68 @@ pub fn handle_module_command(sub: &ModuleSub) -> anyhow::Result<()> {
         ModuleSub::List => {
             modules
                 .iter()
-                .for_each(|(name, path)| println!("{} {path}", format!("{name}").bright_red()));
+                .for_each(|(name, path)| println!("{} {path}", name.bright_red()));
         }
         ModuleSub::Install { name, args } => install_module(name, args)?,
-        ModuleSub::Rm { name } => {
-            let mut cmd = Command::new("rmmod")
-                .arg(name)
-                .stdin(Stdio::inherit())
-                .stdout(Stdio::inherit())
-                .spawn()?;
-            cmd.wait()?;
-        }
+        ModuleSub::Rm { name } => remove_module(name)?,
     }
     Ok(())
 }
 
+/// Installs a module found in the workspace, failing if insmod does, e.g. with EEXIST when the
+/// module is already loaded.
 pub fn install_module(name: &str, args: &[String]) -> anyhow::Result<()> {
-    match modules_ref().get(name) {
-        Some(path) => {
-            let mut cmd = Command::new("insmod")
-                .arg(path)
-                .args(args)
-                .stdin(Stdio::inherit())
-                .stdout(Stdio::inherit())
-                .spawn()?;
-            cmd.wait()?;
-        }
-        None => {
-            eprintln!("Failed to find module: {name}")
-        }
+    let Some(path) = modules_ref().get(name) else {
+        bail!("Failed to find module: {name}");
+    };
+    let mut cmd = Command::new("insmod");
+    cmd.arg(path)
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::piped());
+    let output = executor_ref().output(&mut cmd)?;
+    if !output.status.success() {
+        bail!("Failed to install module {name}: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+/// Whether the kernel has the module loaded, the kernel names it with `_` for `-`.
+pub fn module_loaded(name: &str) -> bool {
+    Path::new("/sys/module").join(name.replace('-', "_")).exists()
+}
+
+pub fn remove_module(name: &str) -> anyhow::Result<()> {
+    let mut cmd = Command::new("rmmod");
+    cmd.arg(name)
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::piped());
+    let output = executor_ref().output(&mut cmd)?;
+    if !output.status.success() {
+        bail!("Failed to remove module {name}: {}", String::from_utf8_lossy(&output.stderr).trim());
     }
     Ok(())
 }
 
-pub fn realm_physical_address() -> anyhow::Result<String> {
-    let provider = File::open("/proc/interface/get_realm_pa")?;
+/// Locks a module for exclusive use, modules are host-global so tests running in parallel
+/// must not load or remove a module another test is using.
+pub async fn lock_module(name: &str) -> tokio::sync::OwnedMutexGuard<()> {
+    let lock = MODULE_LOCKS
+        .get_or_init(Mutex::default)
+        .lock()
+        .unwrap()
+        .entry(name.to_string())
+        .or_default()
+        .clone();
+    lock.lock_owned().await
+}
+
+pub fn realm_physical_address() -> anyhow::Result<u64> {
+    let provider = File::open(REALM_PA_INTERFACE)?;
     let mut reader = BufReader::new(provider);
     let mut addr = String::new();
     reader.read_line(&mut addr)?;
-    Ok(addr)
+    parse_addr(&addr).map_err(|e| anyhow::anyhow!("{REALM_PA_INTERFACE}: {e}"))
 }
\ No newline at end of file
@@ -0,0 +1,215 @@
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use crate::test::TestOutcome;
+
+/// Where kernel module tests leave their results, one directory per test id.
+pub const TEE_TESTS_DIR: &str = "/proc/tee-tests";
+
+/// The result of one named check of a kernel module test.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Subtest {
+    pub name: String,
+    pub outcome: TestOutcome,
+    /// The diagnostic lines the module wrote after the subtest.
+    pub diagnostics: Vec<String>,
+}
+
+/// What a kernel module test left in `/proc/tee-tests/<id>/result`.
+///
+/// The file has one line per subtest, diagnostic lines start with `#` and belong to the
+/// subtest above them, or to the whole module before the first subtest:
+///
+/// ```text
+/// # rmi version 1.0
+/// ok rmi_version
+/// fail rsi_host_call: returned 0x3
+/// # x0 = 0x0000000000000003
+/// skip rmi_pmu: no pmu
+/// ```
+///
+/// A file without any subtest line is a legacy result like `ok`, compared as a whole.
+#[derive(Debug, Clone)]
+pub struct ModuleResult {
+    pub id: usize,
+    pub subtests: Vec<Subtest>,
+    /// Diagnostic lines before the first subtest.
+    pub diagnostics: Vec<String>,
+    /// The trimmed content of a file without subtests.
+    pub legacy: Option<String>,
+}
+
+impl ModuleResult {
+    pub fn path(id: usize) -> PathBuf {
+        PathBuf::from(format!("{TEE_TESTS_DIR}/{id}/result"))
+    }
+
+    /// Reads the result of test `id`, `None` if the module left none.
+    pub fn read(id: usize) -> anyhow::Result<Option<Self>> {
+        let path = Self::path(id);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(&path)?;
+        Ok(Some(Self::parse(id, &content)))
+    }
+
+    pub fn parse(id: usize, content: &str) -> Self {
+        let mut result = Self {
+            id,
+            subtests: Vec::new(),
+            diagnostics: Vec::new(),
+            legacy: None,
+        };
+        let mut other = Vec::new();
+        for line in content.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            if let Some(diagnostic) = line.strip_prefix('#') {
+                let diagnostic = diagnostic.trim().to_string();
+                match result.subtests.last_mut() {
+                    Some(subtest) => subtest.diagnostics.push(diagnostic),
+                    None => result.diagnostics.push(diagnostic),
+                }
+                continue;
+            }
+            match parse_subtest(line) {
+                Some(subtest) => result.subtests.push(subtest),
+                None => other.push(line),
+            }
+        }
+        if result.subtests.is_empty() {
+            result.legacy = Some(other.join("\n"));
+        } else {
+            // A line the format does not know is kept rather than lost.
+            result.diagnostics.extend(other.into_iter().map(str::to_string));
+        }
+        result
+    }
+
+    /// The outcome of the whole module: a legacy result is compared with `expected`, otherwise
+    /// it fails if a subtest failed and is skipped if all subtests were.
+    pub fn outcome(&self, expected: &str) -> TestOutcome {
+        let path = Self::path(self.id);
+        if let Some(legacy) = &self.legacy {
+            if legacy == expected.trim() {
+                return TestOutcome::Pass;
+            }
+            return TestOutcome::fail(format!("{} is `{legacy}`, expected `{}`", path.display(), expected.trim()));
+        }
+
+        let failed = self
+            .subtests
+            .iter()
+            .filter(|subtest| subtest.outcome.is_broken())
+            .map(|subtest| subtest.name.as_str())
+            .collect::<Vec<_>>();
+        if !failed.is_empty() {
+            return TestOutcome::fail(format!(
+                "{} of {} subtests failed: {}",
+                failed.len(),
+                self.subtests.len(),
+                failed.join(", ")
+            ));
+        }
+        if self.subtests.iter().all(|subtest| matches!(subtest.outcome, TestOutcome::Skip(_))) {
+            return TestOutcome::skip(format!("All {} subtests were skipped", self.subtests.len()));
+        }
+        TestOutcome::Pass
+    }
+}
+
+/// Parses `ok <name>`, `fail <name>[: <reason>]` or `skip <name>[: <reason>]`.
+fn parse_subtest(line: &str) -> Option<Subtest> {
+    let (status, rest) = line.split_once(char::is_whitespace)?;
+    let (name, reason) = match rest.split_once(':') {
+        Some((name, reason)) => (name.trim(), reason.trim()),
+        None => (rest.trim(), ""),
+    };
+    if name.is_empty() {
+        return None;
+    }
+    let reason = |default: &str| match reason {
+        "" => default.to_string(),
+        reason => reason.to_string(),
+    };
+    let outcome = match status {
+        "ok" => TestOutcome::Pass,
+        "fail" => TestOutcome::Fail(reason("failed")),
+        "skip" => TestOutcome::Skip(reason("skipped")),
+        _ => return None,
+    };
+    Some(Subtest {
+        name: name.to_string(),
+        outcome,
+        diagnostics: Vec::new(),
+    })
+}
+
+/// The ids of all tests with a result in `/proc/tee-tests`, in ascending order.
+pub fn discover_module_results() -> anyhow::Result<Vec<usize>> {
+    let mut ids = Vec::new();
+    let entries = std::fs::read_dir(TEE_TESTS_DIR)
+        .map_err(|e| anyhow::anyhow!("Failed to read {TEE_TESTS_DIR}, is a test module loaded? {e}"))?;
+    for entry in entries {
+        let entry = entry?;
+        let Ok(id) = entry.file_name().to_string_lossy().parse::<usize>() else {
+            continue;
+        };
+        if ModuleResult::path(id).exists() {
+            ids.push(id);
+        }
+    }
+    ids.sort();
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outcomes(result: &ModuleResult) -> Vec<(&str, &TestOutcome)> {
+        result.subtests.iter().map(|subtest| (subtest.name.as_str(), &subtest.outcome)).collect()
+    }
+
+    #[test]
+    fn parse_subtests() {
+        let content = "# rmi version 1.0\n\
+                       ok rmi_version\n\
+                       fail rsi_host_call: returned 0x3\n\
+                       # x0 = 0x0000000000000003\n\
+                       \n\
+                       skip rmi_pmu: no pmu\n\
+                       fail no_reason\n\
+                       unknown line\n";
+        let result = ModuleResult::parse(1, content);
+        assert_eq!(
+            outcomes(&result),
+            [
+                ("rmi_version", &TestOutcome::Pass),
+                ("rsi_host_call", &TestOutcome::Fail("returned 0x3".to_string())),
+                ("rmi_pmu", &TestOutcome::Skip("no pmu".to_string())),
+                ("no_reason", &TestOutcome::Fail("failed".to_string())),
+            ]
+        );
+        assert_eq!(result.subtests[1].diagnostics, ["x0 = 0x0000000000000003"]);
+        assert_eq!(result.diagnostics, ["rmi version 1.0", "unknown line"]);
+        assert_eq!(result.legacy, None);
+        assert!(matches!(result.outcome("ok"), TestOutcome::Fail(reason) if reason.contains("2 of 4")));
+    }
+
+    #[test]
+    fn parse_legacy() {
+        let result = ModuleResult::parse(1, "  ok\n");
+        assert!(result.subtests.is_empty());
+        assert_eq!(result.legacy.as_deref(), Some("ok"));
+        assert_eq!(result.outcome("ok\n"), TestOutcome::Pass);
+        assert!(matches!(result.outcome("1"), TestOutcome::Fail(_)));
+        // A status without a name is not a subtest.
+        assert_eq!(ModuleResult::parse(1, "ok").legacy.as_deref(), Some("ok"));
+        assert_eq!(ModuleResult::parse(1, "fail : reason").legacy.as_deref(), Some("fail : reason"));
+    }
+
+    #[test]
+    fn module_outcome() {
+        assert_eq!(ModuleResult::parse(1, "ok a\nskip b").outcome("ok"), TestOutcome::Pass);
+        assert!(matches!(ModuleResult::parse(1, "skip a\nskip b").outcome("ok"), TestOutcome::Skip(_)));
+    }
+}
@@ -0,0 +1,478 @@
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+/// The size of a page of physical memory, and of a granule of the GPT.
+pub const PAGE_SIZE: u64 = 4096;
+
+/// Where the worker writes its report, read by its fault handler.
+static REPORT_FD: AtomicI32 = AtomicI32::new(-1);
+/// The virtual and the physical address of the page the worker mapped.
+static MAPPED_VA: AtomicU64 = AtomicU64::new(0);
+static MAPPED_PA: AtomicU64 = AtomicU64::new(0);
+/// Reported as the physical address of a fault outside the mapping of the worker.
+const NO_PA: u64 = u64::MAX;
+
+/// The width of a single access to memory, in bits in json.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(into = "u8", try_from = "u8")]
+pub enum Width {
+    W8,
+    W16,
+    W32,
+    #[default]
+    W64,
+}
+
+impl FromStr for Width {
+    type Err = String;
+
+    /// Parses the width in bits, `8`, `16`, `32` or `64`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "8" => Ok(Width::W8),
+            "16" => Ok(Width::W16),
+            "32" => Ok(Width::W32),
+            "64" => Ok(Width::W64),
+            s => Err(format!("Unknown width: `{s}`, use 8, 16, 32 or 64")),
+        }
+    }
+}
+
+impl From<Width> for u8 {
+    fn from(width: Width) -> Self {
+        width.bits()
+    }
+}
+
+impl TryFrom<u8> for Width {
+    type Error = String;
+
+    fn try_from(bits: u8) -> Result<Self, Self::Error> {
+        bits.to_string().parse()
+    }
+}
+
+impl Width {
+    pub fn bits(&self) -> u8 {
+        self.bytes() as u8 * 8
+    }
+
+    /// The largest value that fits in the width.
+    pub fn mask(&self) -> u64 {
+        u64::MAX >> (64 - self.bits() as u32)
+    }
+
+    pub fn bytes(&self) -> u64 {
+        match self {
+            Width::W8 => 1,
+            Width::W16 => 2,
+            Width::W32 => 4,
+            Width::W64 => 8,
+        }
+    }
+
+    /// Reads exactly this many bits at `ptr` with a single access.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be mapped for reading and aligned to the width.
+    pub unsafe fn read(&self, ptr: *const u8) -> u64 {
+        unsafe {
+            match self {
+                Width::W8 => std::ptr::read_volatile(ptr) as u64,
+                Width::W16 => std::ptr::read_volatile(ptr as *const u16) as u64,
+                Width::W32 => std::ptr::read_volatile(ptr as *const u32) as u64,
+                Width::W64 => std::ptr::read_volatile(ptr as *const u64),
+            }
+        }
+    }
+
+    /// Writes the low bits of `value` at `ptr` with a single access.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be mapped for writing and aligned to the width.
+    pub unsafe fn write(&self, ptr: *mut u8, value: u64) {
+        unsafe {
+            match self {
+                Width::W8 => std::ptr::write_volatile(ptr, value as u8),
+                Width::W16 => std::ptr::write_volatile(ptr as *mut u16, value as u16),
+                Width::W32 => std::ptr::write_volatile(ptr as *mut u32, value as u32),
+                Width::W64 => std::ptr::write_volatile(ptr as *mut u64, value),
+            }
+        }
+    }
+}
+
+/// The access a probe makes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Read(Width),
+    /// Stores the value.
+    Write(Width, u64),
+}
+
+impl Op {
+    pub fn width(&self) -> Width {
+        match self {
+            Op::Read(width) | Op::Write(width, _) => *width,
+        }
+    }
+
+    fn kind(&self) -> AccessKind {
+        match self {
+            Op::Read(_) => AccessKind::Read,
+            Op::Write(..) => AccessKind::Write,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessKind {
+    #[default]
+    Read,
+    Write,
+}
+
+/// What accessing a physical address did, as printed by `tt binary probe` and `tt binary write`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Probe {
+    #[serde(with = "hex")]
+    pub addr: u64,
+    #[serde(default)]
+    pub access: AccessKind,
+    #[serde(default)]
+    pub width: Width,
+    #[serde(flatten)]
+    pub outcome: ProbeOutcome,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "result", rename_all = "kebab-case")]
+pub enum ProbeOutcome {
+    Readable {
+        #[serde(with = "hex")]
+        value: u64,
+    },
+    /// The store completed without a fault.
+    Written {
+        #[serde(with = "hex")]
+        value: u64,
+    },
+    Fault(Fault),
+    MapFailed {
+        errno: i32,
+        error: String,
+    },
+    /// The worker was killed by a signal it does not handle.
+    Killed {
+        signal: i32,
+    },
+}
+
+/// A fault delivered accessing memory.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Fault {
+    pub signal: FaultSignal,
+    pub si_code: i32,
+    /// The name of `si_code`, like `BUS_ADRERR`.
+    pub code: String,
+    /// The faulting virtual address in the worker.
+    #[serde(with = "hex")]
+    pub si_addr: u64,
+    /// The physical address `si_addr` maps to, `None` if it is outside the mapping of the
+    /// probed page.
+    #[serde(with = "hex::option")]
+    pub pa: Option<u64>,
+}
+
+impl Fault {
+    /// Whether the fault came from the granule of `addr`.
+    pub fn in_granule(&self, addr: u64) -> bool {
+        self.pa.is_some_and(|pa| pa / PAGE_SIZE == addr / PAGE_SIZE)
+    }
+}
+
+impl Display for Fault {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.pa {
+            Some(pa) => write!(f, "{} ({}) at {pa:#x}", self.signal, self.code),
+            None => write!(f, "{} ({}) at virtual address {:#x}", self.signal, self.code, self.si_addr),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultSignal {
+    #[serde(rename = "SIGBUS")]
+    SigBus,
+    #[serde(rename = "SIGSEGV")]
+    SigSegv,
+}
+
+impl FaultSignal {
+    fn from_raw(signal: i32) -> Option<Self> {
+        match signal {
+            libc::SIGBUS => Some(FaultSignal::SigBus),
+            libc::SIGSEGV => Some(FaultSignal::SigSegv),
+            _ => None,
+        }
+    }
+
+    /// The name of a `si_code` of this signal, see `include/uapi/asm-generic/siginfo.h`.
+    fn code_name(&self, code: i32) -> String {
+        let name = match (self, code) {
+            (FaultSignal::SigBus, 1) => "BUS_ADRALN",
+            (FaultSignal::SigBus, 2) => "BUS_ADRERR",
+            (FaultSignal::SigBus, 3) => "BUS_OBJERR",
+            (FaultSignal::SigBus, 4) => "BUS_MCEERR_AR",
+            (FaultSignal::SigBus, 5) => "BUS_MCEERR_AO",
+            (FaultSignal::SigSegv, 1) => "SEGV_MAPERR",
+            (FaultSignal::SigSegv, 2) => "SEGV_ACCERR",
+            (FaultSignal::SigSegv, 3) => "SEGV_BNDERR",
+            (FaultSignal::SigSegv, 4) => "SEGV_PKUERR",
+            (FaultSignal::SigSegv, 8) => "SEGV_MTEAERR",
+            (FaultSignal::SigSegv, 9) => "SEGV_MTESERR",
+            (_, 0x80) => "SI_KERNEL",
+            _ => return code.to_string(),
+        };
+        name.to_string()
+    }
+}
+
+impl Display for FaultSignal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FaultSignal::SigBus => write!(f, "SIGBUS"),
+            FaultSignal::SigSegv => write!(f, "SIGSEGV"),
+        }
+    }
+}
+
+impl Display for ProbeOutcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProbeOutcome::Readable { value } => write!(f, "readable with value {value:#x}"),
+            ProbeOutcome::Written { value } => write!(f, "written with value {value:#x}"),
+            ProbeOutcome::Fault(fault) => write!(f, "faulted with {fault}"),
+            ProbeOutcome::MapFailed { error, .. } => write!(f, "mmap failed: {error}"),
+            ProbeOutcome::Killed { signal } => write!(f, "the worker was killed by signal {signal}"),
+        }
+    }
+}
+
+/// What the worker reports through the pipe, plain integers so that it can be written from a
+/// signal handler.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct Report {
+    kind: u64,
+    /// The value read or written, the errno of mmap or the signal.
+    value: u64,
+    si_code: u64,
+    si_addr: u64,
+    pa: u64,
+}
+
+const REPORT_VALUE: u64 = 1;
+const REPORT_MAP_FAILED: u64 = 2;
+const REPORT_FAULT: u64 = 3;
+
+/// Makes a single access to physical address `addr` through `/dev/mem` in a forked worker, so
+/// that a fault is caught and reported instead of killing `tt`.
+pub fn probe(addr: u64, op: Op) -> anyhow::Result<Probe> {
+    let width = op.width();
+    if !addr.is_multiple_of(width.bytes()) {
+        bail!("{addr:#x} is not aligned to {} bytes", width.bytes());
+    }
+    if let Op::Write(_, value) = op
+        && value > width.mask()
+    {
+        bail!("{value:#x} does not fit in {} bits", width.bits());
+    }
+    let mem = File::options()
+        .read(true)
+        .write(matches!(op, Op::Write(..)))
+        .open("/dev/mem")
+        .map_err(|e| anyhow::anyhow!("Failed to open /dev/mem: {e}"))?;
+    let mut fds = [0; 2];
+    // SAFETY: fds has room for the two ends of the pipe.
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        bail!("Failed to create a pipe: {}", std::io::Error::last_os_error());
+    }
+    // SAFETY: pipe2 succeeded, so both are open and owned by nothing else.
+    let (rx, tx) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+
+    // SAFETY: the worker only makes async-signal-safe calls and exits without returning.
+    let pid = unsafe { libc::fork() };
+    if pid < 0 {
+        bail!("Failed to fork a probe worker: {}", std::io::Error::last_os_error());
+    }
+    if pid == 0 {
+        // SAFETY: this is the forked worker.
+        unsafe { worker(mem.as_raw_fd(), addr, op, tx.as_raw_fd()) }
+    }
+    drop(tx);
+    let report = receive(&rx);
+    let mut status = 0;
+    // SAFETY: pid is the worker forked above.
+    unsafe { libc::waitpid(pid, &mut status, 0) };
+
+    let outcome = match report {
+        Some(report) => match report.kind {
+            REPORT_VALUE if op.kind() == AccessKind::Write => ProbeOutcome::Written { value: report.value },
+            REPORT_VALUE => ProbeOutcome::Readable { value: report.value },
+            REPORT_MAP_FAILED => ProbeOutcome::MapFailed {
+                errno: report.value as i32,
+                error: std::io::Error::from_raw_os_error(report.value as i32).to_string(),
+            },
+            _ => {
+                let Some(signal) = FaultSignal::from_raw(report.value as i32) else {
+                    bail!("The probe worker reported an unknown signal {}", report.value);
+                };
+                ProbeOutcome::Fault(Fault {
+                    signal,
+                    si_code: report.si_code as i32,
+                    code: signal.code_name(report.si_code as i32),
+                    si_addr: report.si_addr,
+                    pa: (report.pa != NO_PA).then_some(report.pa),
+                })
+            }
+        },
+        None if libc::WIFSIGNALED(status) => ProbeOutcome::Killed { signal: libc::WTERMSIG(status) },
+        None => bail!("The probe worker exited with {} without a result", libc::WEXITSTATUS(status)),
+    };
+    Ok(Probe { addr, access: op.kind(), width, outcome })
+}
+
+/// Maps the page of `addr`, accesses it and reports what happened to `tx`.
+///
+/// # Safety
+///
+/// Must only run in a forked child: it allocates nothing and exits the process.
+unsafe fn worker(mem: RawFd, addr: u64, op: Op, tx: RawFd) -> ! {
+    let page = addr - addr % PAGE_SIZE;
+    let prot = match op {
+        Op::Read(_) => libc::PROT_READ,
+        Op::Write(..) => libc::PROT_READ | libc::PROT_WRITE,
+    };
+    unsafe {
+        let memory = libc::mmap(
+            std::ptr::null_mut(),
+            PAGE_SIZE as usize,
+            prot,
+            libc::MAP_SHARED,
+            mem,
+            page as libc::off_t,
+        );
+        if memory == libc::MAP_FAILED {
+            let errno = std::io::Error::last_os_error().raw_os_error().unwrap_or_default();
+            send(tx, Report { kind: REPORT_MAP_FAILED, value: errno as u64, ..Report::default() });
+            libc::_exit(0);
+        }
+
+        REPORT_FD.store(tx, Ordering::SeqCst);
+        MAPPED_VA.store(memory as u64, Ordering::SeqCst);
+        MAPPED_PA.store(page, Ordering::SeqCst);
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_fault as *const () as usize;
+        action.sa_flags = libc::SA_SIGINFO;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGBUS, &action, std::ptr::null_mut());
+        libc::sigaction(libc::SIGSEGV, &action, std::ptr::null_mut());
+
+        let ptr = memory.byte_add((addr - page) as usize) as *mut u8;
+        let value = match op {
+            Op::Read(width) => width.read(ptr),
+            Op::Write(width, value) => {
+                width.write(ptr, value);
+                value
+            }
+        };
+        send(tx, Report { kind: REPORT_VALUE, value, ..Report::default() });
+        libc::_exit(0);
+    }
+}
+
+extern "C" fn on_fault(signal: libc::c_int, info: *mut libc::siginfo_t, _: *mut libc::c_void) {
+    // SAFETY: the kernel passes a valid siginfo to a SA_SIGINFO handler.
+    let (si_code, si_addr) = unsafe { ((*info).si_code, (*info).si_addr() as u64) };
+    let va = MAPPED_VA.load(Ordering::SeqCst);
+    let pa = match si_addr.checked_sub(va) {
+        Some(offset) if offset < PAGE_SIZE => MAPPED_PA.load(Ordering::SeqCst) + offset,
+        _ => NO_PA,
+    };
+    let report = Report {
+        kind: REPORT_FAULT,
+        value: signal as u64,
+        si_code: si_code as u64,
+        si_addr,
+        pa,
+    };
+    send(REPORT_FD.load(Ordering::SeqCst), report);
+    // SAFETY: returning would retry the faulting access.
+    unsafe { libc::_exit(0) };
+}
+
+fn send(tx: RawFd, report: Report) {
+    let size = size_of::<Report>();
+    // SAFETY: report is plain data of `size` bytes. A short write is seen as no report.
+    unsafe { libc::write(tx, &report as *const Report as *const libc::c_void, size) };
+}
+
+/// The report of the worker, `None` if it exited without one.
+fn receive(rx: &OwnedFd) -> Option<Report> {
+    let mut report = Report::default();
+    let size = size_of::<Report>();
+    let mut read = 0;
+    while read < size {
+        // SAFETY: the buffer is the rest of `report`, which is plain data.
+        let n = unsafe {
+            libc::read(rx.as_raw_fd(), (&mut report as *mut Report as *mut u8).add(read) as *mut libc::c_void, size - read)
+        };
+        match n {
+            n if n > 0 => read += n as usize,
+            n if n < 0 && std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted => {}
+            _ => return None,
+        }
+    }
+    Some(report)
+}
+
+/// Addresses and values as `0x` hex strings.
+pub mod hex {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{value:#x}"))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        let hex = s.strip_prefix("0x").ok_or_else(|| serde::de::Error::custom(format!("`{s}` is not hex")))?;
+        u64::from_str_radix(hex, 16).map_err(serde::de::Error::custom)
+    }
+
+    pub mod option {
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(value: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+            match value {
+                Some(value) => super::serialize(value, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+            #[derive(Deserialize)]
+            struct Hex(#[serde(with = "super")] u64);
+            Ok(Option::<Hex>::deserialize(deserializer)?.map(|Hex(value)| value))
+        }
+    }
+}
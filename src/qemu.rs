@@ -1,10 +1,24 @@
 use std::collections::HashMap;
 use std::process::{Child, Command, Stdio};
 use std::sync::{Mutex, OnceLock};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use clap::Subcommand;
 use colored::Colorize;
-use log::info;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use crate::binary::parse_addr;
+use crate::client::exec;
+use crate::executor::executor_ref;
+
+/// The kernel image booted by every VMM.
+pub const KERNEL_IMAGE: &str = "/mnt/out/bin/Image";
+/// The initrd booted by every VMM.
+pub const INITRD: &str = "/mnt/out-br/images/rootfs.cpio";
+
+pub const DEFAULT_SMP: u32 = 2;
+pub const DEFAULT_MEMORY: &str = "1G";
+/// How often a booting guest OS is asked whether it is up.
+const BOOT_POLL: Duration = Duration::from_secs(1);
 
 static MANAGER: OnceLock<Mutex<QemuManager>> = OnceLock::new();
 
@@ -20,6 +34,12 @@ pub enum QemuSub {
         typ: QemuType,
         #[clap(short = 's')]
         shared: Option<String>,
+        /// the number of cpus of the vmm.
+        #[clap(long, default_value_t = DEFAULT_SMP)]
+        smp: u32,
+        /// the memory of the vmm, e.g. `1G` or `512M`.
+        #[clap(long, default_value = DEFAULT_MEMORY)]
+        memory: String,
     },
     Stop {
         port: u16,
@@ -29,8 +49,9 @@ pub enum QemuSub {
 pub fn handle_qemu_command(sub: &QemuSub) -> anyhow::Result<()> {
     let mut manager = manager_ref().lock().unwrap();
     match sub {
-        QemuSub::Start { port, typ, shared } => {
-            manager.spawn(*port, *typ, shared.as_ref())?;
+        QemuSub::Start { port, typ, shared, smp, memory } => {
+            let resources = VmmResources { smp: *smp, memory: memory.clone() };
+            manager.spawn(*port, *typ, shared.as_ref(), &resources)?;
         }
         QemuSub::Stop { port } => {
             manager.stop(*port);
@@ -39,12 +60,53 @@ pub fn handle_qemu_command(sub: &QemuSub) -> anyhow::Result<()> {
     Ok(())
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum QemuType {
     Normal,
     Confidential,
 }
 
+impl QemuType {
+    /// How long a freshly spawned vmm of this type needs at most before its guest OS is reachable.
+    pub fn boot_time(&self) -> Duration {
+        match self {
+            QemuType::Normal => Duration::from_mins(1),
+            QemuType::Confidential => Duration::from_mins(3),
+        }
+    }
+
+    fn boot_message(&self) -> &'static str {
+        match self {
+            QemuType::Normal => "Sleep for one minute to wait for vmm running",
+            QemuType::Confidential => "Sleep for three minutes to wait for vmm running",
+        }
+    }
+
+    /// Waits until the guest OS of a freshly spawned vmm at `port` answers, at most its boot
+    /// time, without blocking the async runtime. Returns how long it waited.
+    pub async fn wait_for_boot(&self, port: u16) -> Duration {
+        if executor_ref().is_dry_run() {
+            return Duration::ZERO;
+        }
+        let message = format!("Waiting up to {}s for {self:?} qemu at port {port} to boot", self.boot_time().as_secs());
+        info!("{}", message.bright_red());
+        let start = Instant::now();
+        loop {
+            let probe = tokio::time::timeout(BOOT_POLL, exec("true", port)).await;
+            if matches!(probe, Ok(Ok(_))) {
+                info!("{self:?} qemu at port {port} booted in {}s", start.elapsed().as_secs());
+                return start.elapsed();
+            }
+            if start.elapsed() >= self.boot_time() {
+                warn!("The guest OS at port {port} did not answer in {}s", self.boot_time().as_secs());
+                return start.elapsed();
+            }
+            tokio::time::sleep(BOOT_POLL).await;
+        }
+    }
+}
+
 impl From<String> for QemuType {
     fn from(value: String) -> Self {
         match value.as_str() {
@@ -55,6 +117,22 @@ impl From<String> for QemuType {
     }
 }
 
+/// The cpus and memory of a vmm.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmmResources {
+    pub smp: u32,
+    pub memory: String,
+}
+
+impl Default for VmmResources {
+    fn default() -> Self {
+        Self {
+            smp: DEFAULT_SMP,
+            memory: DEFAULT_MEMORY.to_string(),
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct QemuManager {
     instances: HashMap<u16, QemuGuard>,
@@ -69,48 +147,65 @@ impl QemuManager {
         }
     }
 
+    /// Spawns a vmm and waits until it is running.
     pub fn spawn(
         &mut self,
         port: u16,
         typ: QemuType,
         shared: Option<impl AsRef<str>>,
+        resources: &VmmResources,
+    ) -> anyhow::Result<()> {
+        self.launch(port, typ, shared, resources)?;
+        if executor_ref().is_dry_run() {
+            return Ok(());
+        }
+        info!("{}", typ.boot_message().bright_red());
+        std::thread::sleep(typ.boot_time());
+        info!("{}", format!("Successfully spawned a qemu process with port {port}").bright_red());
+        Ok(())
+    }
+
+    /// Spawns a vmm without waiting for it, see [`QemuType::wait_for_boot`].
+    pub fn launch(
+        &mut self,
+        port: u16,
+        typ: QemuType,
+        shared: Option<impl AsRef<str>>,
+        resources: &VmmResources,
     ) -> anyhow::Result<()> {
         let mut child = Command::new("qemu-system-aarch64");
         child
             .stdin(Stdio::null());
         child
-            .args(basic_vmm_args(port));
+            .args(basic_vmm_args(port, resources));
         if matches!(typ, QemuType::Confidential) {
-            child.args(&confidential_vmm_extra_args());
+            child.args(confidential_vmm_extra_args());
         }
-        if let Some(addr) = shared {
-            child.args(shared_vmm_extra_args(addr.as_ref()));
+        let shared = shared.map(|addr| addr.as_ref().trim().to_string());
+        if let Some(addr) = &shared {
+            child.args(shared_vmm_extra_args(addr)?);
         }
 
         let guard = QemuGuard {
-            instance: child.spawn()?,
+            instance: executor_ref().spawn(&mut child)?,
             port,
             typ,
+            shared,
+            resources: resources.clone(),
         };
         self.instances.insert(port, guard);
-        match typ {
-            QemuType::Normal => {
-                info!("{}", "Sleep for one minute to wait for vmm running".bright_red());
-                std::thread::sleep(Duration::from_mins(1));
-            }
-            QemuType::Confidential => {
-                info!("{}", "Sleep for three minutes to wait for vmm running".red());
-                std::thread::sleep(Duration::from_mins(3));
-            }
-        }
-        info!("{}", format!("Successfully spawned a qemu process with port {port}").bright_red());
         Ok(())
     }
 
-    pub fn spawn_auto_port(&mut self, typ: QemuType, shared: Option<impl AsRef<str>>) -> anyhow::Result<u16> {
+    pub fn launch_auto_port(
+        &mut self,
+        typ: QemuType,
+        shared: Option<impl AsRef<str>>,
+        resources: &VmmResources,
+    ) -> anyhow::Result<u16> {
         let port = self.next_port;
         self.next_port += 1;
-        self.spawn(port, typ, shared)?;
+        self.launch(port, typ, shared, resources)?;
         Ok(port)
     }
 
@@ -118,44 +213,93 @@ impl QemuManager {
         self.instances.remove(&port);
     }
 
-    pub fn find_vmm<F>(&self, predicate: F) -> u16
+    pub fn stop_all(&mut self) {
+        self.instances.clear();
+    }
+
+    /// Stops all vmms sharing `addr`, returns how many were stopped.
+    pub fn stop_sharing(&mut self, addr: &str) -> usize {
+        let before = self.instances.len();
+        self.instances.retain(|_, guard| guard.shared.as_deref() != Some(addr.trim()));
+        before - self.instances.len()
+    }
+
+    /// Takes a vmm out of the manager, it is stopped once the guard is dropped.
+    pub fn take(&mut self, port: u16) -> Option<QemuGuard> {
+        self.instances.remove(&port)
+    }
+
+    pub fn find_vmm<F>(&self, predicate: F) -> Option<u16>
     where
         F: Fn((u16, &QemuGuard)) -> bool,
     {
-        *self
-            .instances
+        self.instances
             .iter()
             .find(|(port, guard)| predicate((**port, guard)))
-            .unwrap()
-            .0
+            .map(|(port, _)| *port)
     }
 
-    pub fn find_normal_vmm(&self) -> u16 {
+    #[allow(dead_code)]
+    pub fn find_normal_vmm(&self) -> Option<u16> {
         self.find_vmm(|(_, guard)| matches!(guard.typ, QemuType::Normal))
     }
 
-    pub fn find_confidential_vmm(&self) -> u16 {
+    #[allow(dead_code)]
+    pub fn find_confidential_vmm(&self) -> Option<u16> {
         self.find_vmm(|(_, guard)| matches!(guard.typ, QemuType::Confidential))
     }
+
+    /// Finds a vmm of `typ` sharing `shared` with `resources`, or launches one.
+    ///
+    /// Returns the port and whether the vmm was just launched and still has to boot.
+    pub fn find_or_launch(
+        &mut self,
+        typ: QemuType,
+        shared: Option<&str>,
+        resources: &VmmResources,
+    ) -> anyhow::Result<(u16, bool)> {
+        let shared = shared.map(str::trim);
+        let found = self.find_vmm(|(_, guard)| {
+            guard.typ == typ && guard.shared.as_deref() == shared && guard.resources == *resources
+        });
+        match found {
+            Some(port) => {
+                info!("Reusing {typ:?} qemu at port {port}");
+                Ok((port, false))
+            }
+            None => Ok((self.launch_auto_port(typ, shared, resources)?, true)),
+        }
+    }
 }
 
 pub struct QemuGuard {
     typ: QemuType,
-    instance: Child,
+    /// `None` if the vmm was not started, see [`crate::executor::Executor::Recording`].
+    instance: Option<Child>,
     port: u16,
+    /// The physical address shared with the guest through ivshmem.
+    shared: Option<String>,
+    resources: VmmResources,
 }
 
 impl Drop for QemuGuard {
     fn drop(&mut self) {
-        if let Err(e) = self.instance.kill() {
-            info!("{}", format!("Failed to stop qemu at port {}", self.port));
+        let Some(instance) = &mut self.instance else {
+            return;
+        };
+        if let Err(e) = instance.kill() {
+            info!("Failed to stop {:?} qemu at port {}: {e}", self.typ, self.port);
             return;
         }
-        info!("{}", format!("Successfully stopped qemu process with port {}", self.port).bright_red());
+        // Reap the child, otherwise it stays around as a zombie until tt exits.
+        if let Err(e) = instance.wait() {
+            info!("Failed to wait for {:?} qemu at port {}: {e}", self.typ, self.port);
+        }
+        info!("{}", format!("Successfully stopped {:?} qemu process with port {}", self.typ, self.port).bright_red());
     }
 }
 
-pub fn basic_vmm_args(port: u16) -> Vec<String> {
+pub fn basic_vmm_args(port: u16, resources: &VmmResources) -> Vec<String> {
     vec![
         "-nodefaults",
         "-chardev", "stdio,mux=on,id=chr0,signal=off",
@@ -169,11 +313,11 @@ pub fn basic_vmm_args(port: u16) -> Vec<String> {
         "-M", "virt",
         "-enable-kvm",
         "-M", "gic-version=3,its=on",
-        "-smp", "2",
-        "-m", "1G",
+        "-smp", &resources.smp.to_string(),
+        "-m", &resources.memory,
         "-nographic",
-        "-kernel", "/mnt/out/bin/Image",
-        "-initrd", "/mnt/out-br/images/rootfs.cpio",
+        "-kernel", KERNEL_IMAGE,
+        "-initrd", INITRD,
         "-append", "console=hvc0",
     ]
         .into_iter()
@@ -192,17 +336,23 @@ pub fn confidential_vmm_extra_args() -> Vec<String> {
         .collect()
 }
 
-pub fn shared_vmm_extra_args(offset: &str) -> Vec<String> {
-    let offset = offset.trim();
-    vec![
+pub fn shared_vmm_extra_args(offset: &str) -> anyhow::Result<Vec<String>> {
+    let offset = match parse_addr(offset) {
+        Ok(offset) => offset.to_string(),
+        // A dry run shares a placeholder for an address it had no provider to ask for.
+        Err(_) if executor_ref().is_dry_run() => offset.trim().to_string(),
+        Err(e) => return Err(e),
+    };
+    Ok(vec![
         "-object", &format!("memory-backend-file,id=physmem,size=4K,mem-path=/dev/mem,offset={offset},share=on"),
         "-device", "ivshmem-plain,memdev=physmem,id=ivshmem0",
     ]
         .into_iter()
         .map(|e| e.to_string())
-        .collect()
+        .collect())
 }
 
+#[allow(dead_code)]
 pub fn vmm_exists(port: u16) -> anyhow::Result<bool> {
     let ps = Command::new("ps")
         .arg("aux")
@@ -225,33 +375,37 @@ pub fn vmm_exists(port: u16) -> anyhow::Result<bool> {
     Ok(code == 0)
 }
 
+#[allow(dead_code)]
 pub async fn start_normal_vmm_if_no_exists(args: &[String], port: u16) -> anyhow::Result<Option<Child>> {
     if vmm_exists(port)? {
         return Ok(None);
     }
 
-    let cmd = Command::new("qemu-system-aarch64")
-        .args(basic_vmm_args(port))
-        .args(args)
-        .spawn()?;
+    let cmd = executor_ref().spawn(
+        Command::new("qemu-system-aarch64")
+            .args(basic_vmm_args(port, &VmmResources::default()))
+            .args(args),
+    )?;
 
     info!("{}", "Sleeping for 45 seconds to wait for vmm.".bright_red());
     tokio::time::sleep(Duration::from_secs(45)).await;
-    Ok(Some(cmd))
+    Ok(cmd)
 }
 
+#[allow(dead_code)]
 pub async fn start_confidential_vmm_if_no_exists(args: &[String], port: u16) -> anyhow::Result<Option<Child>> {
     if vmm_exists(port)? {
         return Ok(None);
     }
 
-    let cmd = Command::new("qemu-system-aarch64")
-        .args(basic_vmm_args(port))
-        .args(confidential_vmm_extra_args())
-        .args(args)
-        .spawn()?;
+    let cmd = executor_ref().spawn(
+        Command::new("qemu-system-aarch64")
+            .args(basic_vmm_args(port, &VmmResources::default()))
+            .args(confidential_vmm_extra_args())
+            .args(args),
+    )?;
 
     info!("{}", "Sleeping for one minute to wait for vmm.".bright_red());
     tokio::time::sleep(Duration::from_secs(60)).await;
-    Ok(Some(cmd))
-}
\ No newline at end of file
+    Ok(cmd)
+}
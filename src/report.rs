@@ -0,0 +1,555 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use clap::Subcommand;
+use colored::Colorize;
+use log::info;
+use serde::{Deserialize, Serialize};
+use crate::client::ExecRes;
+use crate::module_result::Subtest;
+use crate::qemu::{QemuType, INITRD, KERNEL_IMAGE};
+use crate::test::{GuestResult, TestContext, TestOutcome};
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum ReportSub {
+    /// list regressions, fixes, newly skipped, added and removed tests between two json reports.
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+    },
+}
+
+pub fn handle_report_command(sub: &ReportSub) -> anyhow::Result<ExitCode> {
+    match sub {
+        ReportSub::Diff { old, new } => {
+            let (old, new) = (Report::load(old)?, Report::load(new)?);
+            let diff = ReportDiff::new(&old, &new);
+            diff.print();
+            // A test added broken needs a look as much as one that regressed.
+            if diff.regressions.is_empty() && !diff.added.iter().any(|result| result.outcome.is_broken()) {
+                return Ok(ExitCode::SUCCESS);
+            }
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+/// The result of one executed test, as written to reports.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TestResult {
+    pub id: usize,
+    pub name: String,
+    /// The variant of the test this is the result of.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    /// The subtest of a kernel module test this is the result of.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subtest: Option<String>,
+    pub outcome: TestOutcome,
+    #[serde(rename = "duration_secs", with = "secs")]
+    pub duration: Duration,
+    /// How long the vmms the test launched took to boot, part of the duration.
+    #[serde(rename = "boot_secs", default, skip_serializing_if = "Option::is_none", with = "secs::option")]
+    pub boot: Option<Duration>,
+    pub vmm: Option<QemuType>,
+    pub kernel: Option<String>,
+    pub initrd: Option<String>,
+    pub stdout: String,
+    pub stderr: String,
+    pub guest: Option<ExecRes>,
+    pub guest_result: Option<GuestResult>,
+    /// The failed attempts before this one, oldest first, if the test was retried.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attempts: Vec<Attempt>,
+}
+
+impl TestResult {
+    pub fn new(ctx: TestContext, outcome: TestOutcome, duration: Duration) -> Self {
+        let vmm = ctx.case().vmm();
+        Self {
+            id: ctx.test.id,
+            name: ctx.test.name.to_string(),
+            variant: ctx.variant.map(|variant| variant.name.to_string()),
+            subtest: None,
+            outcome,
+            duration,
+            boot: ctx.boot,
+            vmm,
+            kernel: vmm.map(|_| KERNEL_IMAGE.to_string()),
+            initrd: vmm.map(|_| INITRD.to_string()),
+            stdout: ctx.stdout,
+            stderr: ctx.stderr,
+            guest: ctx.guest,
+            guest_result: ctx.guest_result,
+            attempts: Vec::new(),
+        }
+    }
+
+    /// The result of a kernel module test read from `/proc/tee-tests` outside of a run.
+    pub fn module(id: usize, name: String, outcome: TestOutcome, stdout: String) -> Self {
+        Self {
+            id,
+            name,
+            variant: None,
+            subtest: None,
+            outcome,
+            duration: Duration::ZERO,
+            boot: None,
+            vmm: None,
+            kernel: None,
+            initrd: None,
+            stdout,
+            stderr: String::new(),
+            guest: None,
+            guest_result: None,
+            attempts: Vec::new(),
+        }
+    }
+
+    /// This result followed by one result per subtest, which take no time of their own.
+    pub fn with_subtests(self, subtests: &[Subtest]) -> Vec<Self> {
+        let mut results = subtests
+            .iter()
+            .map(|subtest| Self {
+                id: self.id,
+                name: self.name.clone(),
+                variant: self.variant.clone(),
+                subtest: Some(subtest.name.clone()),
+                outcome: subtest.outcome.clone(),
+                duration: Duration::ZERO,
+                boot: None,
+                vmm: self.vmm,
+                kernel: self.kernel.clone(),
+                initrd: self.initrd.clone(),
+                stdout: subtest.diagnostics.iter().map(|line| format!("{line}\n")).collect(),
+                stderr: String::new(),
+                guest: None,
+                guest_result: None,
+                attempts: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+        results.insert(0, self);
+        results
+    }
+
+    /// Whether the test passed only after failing before.
+    pub fn is_flaky(&self) -> bool {
+        self.outcome == TestOutcome::Pass && !self.attempts.is_empty()
+    }
+
+    /// The output of the host stage followed by the output of the guest stage.
+    fn output(&self) -> (String, String) {
+        output(&self.stdout, &self.stderr, self.guest.as_ref(), self.guest_result.as_ref())
+    }
+
+    /// `<id> (<name>)`, followed by `[<variant>]` for a variant and `/<subtest>` for a subtest.
+    pub fn label(&self) -> String {
+        let mut label = format!("{} ({})", self.id, self.name);
+        if let Some(variant) = &self.variant {
+            write!(label, "[{variant}]").unwrap();
+        }
+        if let Some(subtest) = &self.subtest {
+            write!(label, "/{subtest}").unwrap();
+        }
+        label
+    }
+
+    /// What identifies the result across reports.
+    fn key(&self) -> (usize, Option<&str>, Option<&str>) {
+        (self.id, self.variant.as_deref(), self.subtest.as_deref())
+    }
+}
+
+/// An attempt of a retried test.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Attempt {
+    pub outcome: TestOutcome,
+    #[serde(rename = "duration_secs", with = "secs")]
+    pub duration: Duration,
+    pub stdout: String,
+    pub stderr: String,
+    pub guest: Option<ExecRes>,
+    pub guest_result: Option<GuestResult>,
+}
+
+impl Attempt {
+    fn output(&self) -> (String, String) {
+        output(&self.stdout, &self.stderr, self.guest.as_ref(), self.guest_result.as_ref())
+    }
+}
+
+impl From<TestResult> for Attempt {
+    fn from(result: TestResult) -> Self {
+        Self {
+            outcome: result.outcome,
+            duration: result.duration,
+            stdout: result.stdout,
+            stderr: result.stderr,
+            guest: result.guest,
+            guest_result: result.guest_result,
+        }
+    }
+}
+
+fn output(
+    stdout: &str,
+    stderr: &str,
+    guest: Option<&ExecRes>,
+    guest_result: Option<&GuestResult>,
+) -> (String, String) {
+    let (mut stdout, mut stderr) = (stdout.to_string(), stderr.to_string());
+    let guest = guest_result
+        .map(|guest| (&guest.stdout, &guest.stderr))
+        .or(guest.map(|guest| (&guest.stdout, &guest.stderr)));
+    if let Some((guest_stdout, guest_stderr)) = guest {
+        write!(stdout, "\n--- guest stdout ---\n{guest_stdout}").unwrap();
+        write!(stderr, "\n--- guest stderr ---\n{guest_stderr}").unwrap();
+    }
+    (stdout, stderr)
+}
+
+pub mod secs {
+    use std::time::Duration;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        from_secs::<D>(f64::deserialize(deserializer)?)
+    }
+
+    /// A report written by something else may hold a negative or non-finite duration.
+    fn from_secs<'de, D: Deserializer<'de>>(secs: f64) -> Result<Duration, D::Error> {
+        Duration::try_from_secs_f64(secs).map_err(|e| D::Error::custom(format!("Invalid duration {secs}: {e}")))
+    }
+
+    pub mod option {
+        use std::time::Duration;
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+            match duration {
+                Some(duration) => super::serialize(duration, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+            Option::<f64>::deserialize(deserializer)?.map(super::from_secs::<D>).transpose()
+        }
+    }
+}
+
+/// A json report of a whole run.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Report {
+    /// Seconds since the unix epoch when the report was written.
+    pub created: u64,
+    pub results: Vec<TestResult>,
+}
+
+impl Report {
+    pub fn new(results: &[TestResult]) -> Self {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        Self {
+            created,
+            results: results.to_vec(),
+        }
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read report {}: {e}", path.display()))?;
+        Ok(serde_json::from_str(&content)?)
+    }
+}
+
+/// Changes of outcomes between two reports, keyed by test id, variant and subtest.
+pub struct ReportDiff<'a> {
+    pub regressions: Vec<(&'a TestResult, &'a TestResult)>,
+    pub fixes: Vec<(&'a TestResult, &'a TestResult)>,
+    pub skipped: Vec<(&'a TestResult, &'a TestResult)>,
+    /// Results only in the new report.
+    pub added: Vec<&'a TestResult>,
+    /// Results only in the old report.
+    pub removed: Vec<&'a TestResult>,
+}
+
+impl<'a> ReportDiff<'a> {
+    pub fn new(old: &'a Report, new: &'a Report) -> Self {
+        let old_results = old
+            .results
+            .iter()
+            .map(|result| (result.key(), result))
+            .collect::<HashMap<_, _>>();
+        let mut diff = Self {
+            regressions: Vec::new(),
+            fixes: Vec::new(),
+            skipped: Vec::new(),
+            added: Vec::new(),
+            removed: Vec::new(),
+        };
+
+        for new in &new.results {
+            let Some(old) = old_results.get(&new.key()).copied() else {
+                diff.added.push(new);
+                continue;
+            };
+            match (&old.outcome, &new.outcome) {
+                (TestOutcome::Pass, outcome) if outcome.is_broken() => diff.regressions.push((old, new)),
+                (outcome, TestOutcome::Pass) if outcome.is_broken() => diff.fixes.push((old, new)),
+                (TestOutcome::Skip(_), TestOutcome::Skip(_)) => {}
+                (_, TestOutcome::Skip(_)) => diff.skipped.push((old, new)),
+                _ => {}
+            }
+        }
+        let new_keys = new.results.iter().map(TestResult::key).collect::<HashSet<_>>();
+        diff.removed = old.results.iter().filter(|old| !new_keys.contains(&old.key())).collect();
+        diff
+    }
+
+    pub fn print(&self) {
+        let sections = [
+            ("Regressions", &self.regressions),
+            ("Fixes", &self.fixes),
+            ("Newly skipped", &self.skipped),
+        ];
+        for (title, changes) in sections {
+            println!("{} ({}):", title.bright_red(), changes.len());
+            for (old, new) in changes {
+                println!("  {}: {} -> {}", new.label(), old.outcome, new.outcome);
+            }
+        }
+        for (title, results) in [("Added", &self.added), ("Removed", &self.removed)] {
+            println!("{} ({}):", title.bright_red(), results.len());
+            for result in results {
+                println!("  {}: {}", result.label(), result.outcome);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ReportFormat {
+    Junit,
+    Json,
+}
+
+/// Where and how to write a report, parsed from `<format>:<path>`.
+#[derive(Debug, Clone)]
+pub struct ReportTarget {
+    format: ReportFormat,
+    path: PathBuf,
+}
+
+impl FromStr for ReportTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((format, path)) = s.split_once(':') else {
+            return Err(format!("A report must be `<format>:<path>`, got `{s}`"));
+        };
+        let format = match format {
+            "junit" => ReportFormat::Junit,
+            "json" => ReportFormat::Json,
+            _ => return Err(format!("Unknown report format: {format}")),
+        };
+        Ok(Self { format, path: path.into() })
+    }
+}
+
+impl ReportTarget {
+    pub fn write(&self, results: &[TestResult]) -> anyhow::Result<()> {
+        let content = match self.format {
+            ReportFormat::Junit => junit(results),
+            ReportFormat::Json => serde_json::to_string_pretty(&Report::new(results))?,
+        };
+        std::fs::write(&self.path, content)?;
+        info!("Report written to {}", self.path.display());
+        Ok(())
+    }
+}
+
+fn junit(results: &[TestResult]) -> String {
+    // A test with subtests is reported by its subtests alone, so that no failure counts twice.
+    let with_subtests = results
+        .iter()
+        .filter(|r| r.subtest.is_some())
+        .map(|r| (r.id, r.variant.as_deref()))
+        .collect::<HashSet<_>>();
+    let results = results
+        .iter()
+        .filter(|r| r.subtest.is_some() || !with_subtests.contains(&(r.id, r.variant.as_deref())))
+        .collect::<Vec<_>>();
+    let count = |f: fn(&TestOutcome) -> bool| results.iter().filter(|r| f(&r.outcome)).count();
+    let failures = count(|o| matches!(o, TestOutcome::Fail(_)));
+    let errors = count(|o| matches!(o, TestOutcome::Error(_) | TestOutcome::Timeout(_)));
+    let skipped = count(|o| matches!(o, TestOutcome::Skip(_)));
+    let time: f64 = results.iter().map(|r| r.duration.as_secs_f64()).sum();
+
+    let mut xml = String::new();
+    writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+    writeln!(xml, "<testsuites>").unwrap();
+    writeln!(
+        xml,
+        r#"  <testsuite name="tt" tests="{}" failures="{failures}" errors="{errors}" skipped="{skipped}" time="{time:.3}">"#,
+        results.len(),
+    ).unwrap();
+    for result in results {
+        let mut name = format!("{} {}", result.id, result.name);
+        if let Some(variant) = &result.variant {
+            write!(name, "[{variant}]").unwrap();
+        }
+        if let Some(subtest) = &result.subtest {
+            write!(name, "/{subtest}").unwrap();
+        }
+        writeln!(
+            xml,
+            r#"    <testcase classname="tt" name="{}" time="{:.3}">"#,
+            escape(&name),
+            result.duration.as_secs_f64(),
+        ).unwrap();
+        match &result.outcome {
+            TestOutcome::Pass => {}
+            TestOutcome::Fail(reason) => {
+                writeln!(xml, r#"      <failure message="{}"/>"#, escape(reason)).unwrap();
+            }
+            TestOutcome::Skip(reason) => {
+                writeln!(xml, r#"      <skipped message="{}"/>"#, escape(reason)).unwrap();
+            }
+            TestOutcome::Error(reason) => {
+                writeln!(xml, r#"      <error message="{}"/>"#, escape(reason)).unwrap();
+            }
+            TestOutcome::Timeout(_) => {
+                writeln!(xml, r#"      <error type="timeout" message="{}"/>"#, result.outcome).unwrap();
+            }
+        }
+
+        // Like surefire, earlier attempts of a test that passed in the end are flaky failures,
+        // otherwise they are reruns.
+        let prefix = if result.outcome == TestOutcome::Pass { "flaky" } else { "rerun" };
+        for attempt in &result.attempts {
+            let kind = match attempt.outcome {
+                TestOutcome::Fail(_) => "Failure",
+                _ => "Error",
+            };
+            let (stdout, stderr) = attempt.output();
+            writeln!(xml, r#"      <{prefix}{kind} message="{}">"#, escape(&attempt.outcome.to_string())).unwrap();
+            writeln!(xml, "        <system-out>{}</system-out>", escape(&stdout)).unwrap();
+            writeln!(xml, "        <system-err>{}</system-err>", escape(&stderr)).unwrap();
+            writeln!(xml, "      </{prefix}{kind}>").unwrap();
+        }
+
+        let (stdout, stderr) = result.output();
+        writeln!(xml, "      <system-out>{}</system-out>", escape(&stdout)).unwrap();
+        writeln!(xml, "      <system-err>{}</system-err>", escape(&stderr)).unwrap();
+        writeln!(xml, "    </testcase>").unwrap();
+    }
+    writeln!(xml, "  </testsuite>").unwrap();
+    writeln!(xml, "</testsuites>").unwrap();
+    xml
+}
+
+/// Escapes text for XML, dropping the control characters XML 1.0 cannot hold.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(results: &[(usize, TestOutcome)]) -> Report {
+        let results = results
+            .iter()
+            .map(|(id, outcome)| TestResult::module(*id, format!("test-{id}"), outcome.clone(), String::new()))
+            .collect::<Vec<_>>();
+        Report::new(&results)
+    }
+
+    fn ids(results: &[&TestResult]) -> Vec<usize> {
+        results.iter().map(|result| result.id).collect()
+    }
+
+    fn pair_ids(changes: &[(&TestResult, &TestResult)]) -> Vec<usize> {
+        changes.iter().map(|(_, new)| new.id).collect()
+    }
+
+    #[test]
+    fn diff_classifies_changes() {
+        let fail = || TestOutcome::Fail("fail".to_string());
+        let skip = || TestOutcome::Skip("skip".to_string());
+        let old = report(&[
+            (1, TestOutcome::Pass),
+            (2, fail()),
+            (3, TestOutcome::Pass),
+            (4, skip()),
+            (5, TestOutcome::Pass),
+            (6, fail()),
+        ]);
+        let new = report(&[
+            (1, fail()),
+            (2, TestOutcome::Pass),
+            (3, skip()),
+            (4, skip()),
+            (5, TestOutcome::Pass),
+            (7, TestOutcome::Error("error".to_string())),
+        ]);
+        let diff = ReportDiff::new(&old, &new);
+        assert_eq!(pair_ids(&diff.regressions), [1]);
+        assert_eq!(pair_ids(&diff.fixes), [2]);
+        assert_eq!(pair_ids(&diff.skipped), [3]);
+        assert_eq!(ids(&diff.added), [7]);
+        assert_eq!(ids(&diff.removed), [6]);
+    }
+
+    #[test]
+    fn diff_keys_by_subtest() {
+        let mut old = report(&[(1, TestOutcome::Pass), (1, TestOutcome::Pass)]);
+        old.results[1].subtest = Some("a".to_string());
+        let mut new = report(&[(1, TestOutcome::Pass), (1, TestOutcome::Timeout(1))]);
+        new.results[1].subtest = Some("a".to_string());
+        let diff = ReportDiff::new(&old, &new);
+        assert_eq!(diff.regressions.len(), 1);
+        assert_eq!(diff.regressions[0].1.subtest.as_deref(), Some("a"));
+        assert!(diff.added.is_empty() && diff.removed.is_empty());
+    }
+
+    #[test]
+    fn invalid_durations_are_errors() {
+        let result = serde_json::to_value(TestResult::module(1, "test-1".to_string(), TestOutcome::Pass, String::new()))
+            .unwrap();
+        for duration in [serde_json::json!(1.5), serde_json::json!(0)] {
+            let mut result = result.clone();
+            result["duration_secs"] = duration;
+            assert!(serde_json::from_value::<TestResult>(result).is_ok());
+        }
+        for duration in [serde_json::json!(-1.0), serde_json::json!(1e300)] {
+            let mut result = result.clone();
+            result["duration_secs"] = duration;
+            assert!(serde_json::from_value::<TestResult>(result).is_err());
+        }
+    }
+}
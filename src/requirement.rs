@@ -0,0 +1,165 @@
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
+use log::warn;
+use crate::module::{modules_ref, REALM_PA_INTERFACE};
+
+/// Something the environment must provide for a test to run, a test is skipped if it does not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Requirement {
+    /// `/dev/kvm` can be opened.
+    Kvm,
+    /// KVM can run realms, it reports `KVM_CAP_ARM_RME`.
+    Rme,
+    /// tt runs as root.
+    Root,
+    /// The kernel is built without `CONFIG_STRICT_DEVMEM`, so `/dev/mem` reaches all memory.
+    DevMem,
+    /// The `.ko` of a module is in the workspace.
+    Module(&'static str),
+    /// `qemu-system-aarch64` is in `PATH`.
+    Qemu,
+    /// The interface of the realm PA provider, checked once the modules of the test are installed.
+    RealmPaProvider,
+}
+
+impl Display for Requirement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Requirement::Kvm => write!(f, "kvm"),
+            Requirement::Rme => write!(f, "rme"),
+            Requirement::Root => write!(f, "root"),
+            Requirement::DevMem => write!(f, "devmem"),
+            Requirement::Module(name) => write!(f, "module:{name}"),
+            Requirement::Qemu => write!(f, "qemu"),
+            Requirement::RealmPaProvider => write!(f, "realm-pa"),
+        }
+    }
+}
+
+impl FromStr for Requirement {
+    type Err = String;
+
+    /// Parses `kvm`, `rme`, `root`, `devmem`, `module:<name>`, `qemu` or `realm-pa`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "kvm" => Ok(Requirement::Kvm),
+            "rme" => Ok(Requirement::Rme),
+            "root" => Ok(Requirement::Root),
+            "devmem" => Ok(Requirement::DevMem),
+            "qemu" => Ok(Requirement::Qemu),
+            "realm-pa" => Ok(Requirement::RealmPaProvider),
+            s => match s.strip_prefix("module:") {
+                Some(name) if !name.is_empty() => Ok(Requirement::Module(intern(name))),
+                _ => Err(format!(
+                    "Unknown requirement: `{s}`, use kvm, rme, root, devmem, module:<name>, qemu or realm-pa"
+                )),
+            },
+        }
+    }
+}
+
+/// The names of modules required by parsed requirements, leaked once each.
+static MODULE_NAMES: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+
+/// A `'static` copy of the name of a module, the same one however often it is parsed.
+fn intern(name: &str) -> &'static str {
+    let mut names = MODULE_NAMES.get_or_init(Mutex::default).lock().unwrap();
+    match names.get(name) {
+        Some(name) => name,
+        None => {
+            let name = &*Box::leak(name.to_string().into_boxed_str());
+            names.insert(name);
+            name
+        }
+    }
+}
+
+impl Requirement {
+    /// Why the environment does not meet the requirement, `None` if it does.
+    pub fn unmet(&self) -> Option<String> {
+        match self {
+            Requirement::Kvm => {
+                let kvm = std::fs::OpenOptions::new().read(true).write(true).open("/dev/kvm");
+                kvm.err().map(|e| format!("/dev/kvm is not usable: {e}"))
+            }
+            Requirement::Rme => match kvm_rme() {
+                Ok(true) => None,
+                Ok(false) => Some("KVM cannot run realms, it does not report KVM_CAP_ARM_RME".to_string()),
+                Err(e) => Some(format!("/dev/kvm is not usable: {e}")),
+            },
+            Requirement::Root => {
+                // SAFETY: geteuid has no preconditions and cannot fail.
+                let euid = unsafe { libc::geteuid() };
+                (euid != 0).then(|| format!("tt runs as uid {euid}, not as root"))
+            }
+            Requirement::DevMem => match strict_devmem() {
+                Some(true) => Some("the kernel is built with CONFIG_STRICT_DEVMEM".to_string()),
+                Some(false) => None,
+                None => {
+                    warn!("Cannot tell whether the kernel is built with CONFIG_STRICT_DEVMEM");
+                    None
+                }
+            },
+            Requirement::Module(name) => {
+                (!modules_ref().contains_key(*name)).then(|| format!("module {name}.ko is not in the workspace"))
+            }
+            Requirement::Qemu => {
+                (!in_path("qemu-system-aarch64")).then(|| "qemu-system-aarch64 is not in PATH".to_string())
+            }
+            Requirement::RealmPaProvider => {
+                (!Path::new(REALM_PA_INTERFACE).exists())
+                    .then(|| format!("{REALM_PA_INTERFACE} does not exist, the realm PA provider is not loaded"))
+            }
+        }
+    }
+
+    /// Whether the requirement can only be checked after the modules of the test are installed.
+    pub fn after_modules(&self) -> bool {
+        matches!(self, Requirement::RealmPaProvider)
+    }
+}
+
+/// `KVM_CHECK_EXTENSION`, `_IO(KVMIO, 0x03)`.
+const KVM_CHECK_EXTENSION: libc::c_ulong = 0xae03;
+/// The capability of a host kernel with the Arm CCA patches that can run realms.
+const KVM_CAP_ARM_RME: libc::c_int = 300;
+
+/// Whether KVM reports the capability to run realms.
+fn kvm_rme() -> std::io::Result<bool> {
+    let kvm = std::fs::OpenOptions::new().read(true).write(true).open("/dev/kvm")?;
+    // SAFETY: KVM_CHECK_EXTENSION takes an integer and only reads it.
+    let supported = unsafe { libc::ioctl(kvm.as_raw_fd(), KVM_CHECK_EXTENSION as _, KVM_CAP_ARM_RME) };
+    if supported < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(supported > 0)
+}
+
+/// Whether the running kernel restricts `/dev/mem`, `None` if its config is not available.
+fn strict_devmem() -> Option<bool> {
+    let release = std::fs::read_to_string("/proc/sys/kernel/osrelease").ok()?;
+    let config = match std::fs::read_to_string(format!("/boot/config-{}", release.trim())) {
+        Ok(config) => config,
+        Err(_) => {
+            // Only reads the config, so a dry run runs it too instead of recording it.
+            let output = Command::new("zcat").arg("/proc/config.gz").output().ok()?;
+            if !output.status.success() {
+                return None;
+            }
+            String::from_utf8_lossy(&output.stdout).to_string()
+        }
+    };
+    Some(config.lines().any(|line| line.trim() == "CONFIG_STRICT_DEVMEM=y"))
+}
+
+fn in_path(program: &str) -> bool {
+    let Some(path) = std::env::var_os("PATH") else {
+        return false;
+    };
+    std::env::split_paths(&path).any(|dir| dir.join(program).is_file())
+}
@@ -0,0 +1,280 @@
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use anyhow::bail;
+use colored::Colorize;
+use serde::Serialize;
+use crate::binary::parse_addr;
+use crate::executor::{executor_ref, record};
+use crate::qemu::QemuType;
+use crate::test::tests_ref;
+
+/// The line of `src/cases.rs` new registry entries are inserted above.
+const REGISTRY_MARKER: &str = "// `tt test new` inserts new tests above this line.";
+
+/// A test scaffolded by `tt test new`.
+pub struct NewTest {
+    pub id: usize,
+    pub name: String,
+    pub vmm: Option<QemuType>,
+    /// `realm-pa` or a physical address.
+    pub shared: Option<String>,
+}
+
+impl NewTest {
+    /// Checks the name is kebab-case and neither the name nor the id is taken by a registered
+    /// test or one already added to `src`, the id is the next free one if absent.
+    pub fn new(
+        name: &str,
+        id: Option<usize>,
+        vmm: Option<QemuType>,
+        shared: Option<&str>,
+        src: &Path,
+    ) -> anyhow::Result<Self> {
+        let kebab = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-';
+        if name.is_empty() || name.starts_with('-') || !name.chars().all(kebab) {
+            bail!("A test name must be kebab-case, like `realm-pa-host-read`, got `{name}`");
+        }
+        let mut taken = tests_ref().iter().map(|test| (test.id, test.name.to_string())).collect::<Vec<_>>();
+        // Tests added since tt was built are only in the source.
+        taken.extend(declared_tests(&std::fs::read_to_string(src).unwrap_or_default()));
+        if taken.iter().any(|(_, taken)| taken == name) {
+            bail!("Test {name} already exists");
+        }
+        let id = match id {
+            Some(id) if taken.iter().any(|(taken, _)| *taken == id) => bail!("Test {id} already exists"),
+            Some(id) => id,
+            None => taken.iter().map(|(id, _)| *id).max().unwrap_or(0) + 1,
+        };
+        // The address is pasted into the generated code, so it is checked now rather than when
+        // the test runs.
+        let shared = match shared {
+            None | Some("realm-pa") => shared.map(str::to_string),
+            Some(addr) => match parse_addr(addr) {
+                Ok(addr) => Some(format!("{addr:#x}")),
+                Err(e) => bail!("--shared must be `realm-pa` or a physical address: {e:#}"),
+            },
+        };
+        Ok(Self {
+            id,
+            name: name.to_string(),
+            vmm,
+            shared,
+        })
+    }
+
+    /// Adds the test to `src` and writes its manifest stub, checking first that the stub does
+    /// not exist so that nothing is written half. A dry run only records both.
+    pub fn write(&self, src: &Path) -> anyhow::Result<()> {
+        let manifest = self.manifest_path();
+        if manifest.exists() {
+            bail!("{} already exists", manifest.display());
+        }
+        if executor_ref().is_dry_run() {
+            record(&format!("add test {} ({}) to {}", self.id, self.name, src.display()));
+            if self.manifest().is_some() {
+                record(&format!("write the manifest stub of test {} to {}", self.id, manifest.display()));
+            }
+            return Ok(());
+        }
+        self.write_rust(src)?;
+        self.write_manifest()?;
+        Ok(())
+    }
+
+    fn manifest_path(&self) -> PathBuf {
+        PathBuf::from(format!("{}.test.json", self.name))
+    }
+
+    fn realm_pa(&self) -> bool {
+        self.shared.as_deref() == Some("realm-pa")
+    }
+
+    /// Adds the registry entry and the stages of the test to `src`, usually `src/cases.rs`.
+    pub fn write_rust(&self, src: &Path) -> anyhow::Result<()> {
+        let content = std::fs::read_to_string(src)
+            .map_err(|e| anyhow::anyhow!("Failed to read {}, run in the tt source tree or pass --src: {e}", src.display()))?;
+        let Some(marker) = content.find(REGISTRY_MARKER) else {
+            bail!("{} has no line `{REGISTRY_MARKER}`", src.display());
+        };
+        let line = content[..marker].rfind('\n').map(|i| i + 1).unwrap_or(0);
+
+        let mut content = format!("{}{}{}", &content[..line], self.registry_entry(), &content[line..]);
+        if !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content.push_str(&self.stages());
+        std::fs::write(src, content)?;
+        info(&format!("Added test {} ({}) to {}", self.id, self.name, src.display()));
+        Ok(())
+    }
+
+    fn registry_entry(&self) -> String {
+        let id = self.id;
+        let mut entry = format!("        TestCase::new({id}, \"{}\", test_{id})\n", self.name);
+        entry.push_str("            .description(\"\")\n");
+        if let Some(typ) = self.vmm {
+            writeln!(entry, "            .vmm(QemuType::{typ:?})").unwrap();
+        }
+        match (self.vmm, &self.shared) {
+            (None, None) => writeln!(entry, "            .modules(&[\"test_{id}\"])").unwrap(),
+            _ if self.realm_pa() => entry.push_str("            .modules(&[\"realm_pa_provider\"])\n"),
+            _ => {}
+        }
+        match self.shared.as_deref() {
+            Some("realm-pa") => entry.push_str("            .shared(Shared::RealmPa)\n"),
+            Some(addr) => writeln!(entry, "            .shared(Shared::Address(\"{addr}\"))").unwrap(),
+            None => {}
+        }
+        if self.vmm.is_none() && self.shared.is_some() {
+            entry.push_str("            .requires(&[Requirement::Root, Requirement::DevMem])\n");
+        }
+        if self.vmm.is_some() {
+            writeln!(entry, "            .guest(test_{id}_guest)").unwrap();
+        }
+        entry.pop();
+        entry.push_str(",\n");
+        entry
+    }
+
+    fn stages(&self) -> String {
+        let id = self.id;
+        let not_implemented = format!("Ok(crate::test::TestOutcome::skip(\"Test {id} is not implemented yet\"))");
+        let mut stages = String::new();
+        match (self.vmm, &self.shared) {
+            (Some(_), shared) => {
+                write!(stages, "
+/// The host stage of test {id}.
+fn test_{id}(ctx: &mut TestContext) -> StageFuture<'_> {{
+    Box::pin(async move {{
+        let port = ctx.vmm().await?;
+        upload_tt(ctx, port).await?;
+
+        ctx.exec_guest(port).await
+    }})
+}}
+").unwrap();
+                match shared {
+                    Some(_) => write!(stages, "
+/// The guest stage of test {id}.
+fn test_{id}_guest(ctx: &mut TestContext) -> StageFuture<'_> {{
+    Box::pin(async move {{
+        let pa = pa_from_shared(DEFAULT_SHARED_ADDR)?;
+        expect_access(ctx, &pa, Access::SigBus).await
+    }})
+}}
+").unwrap(),
+                    None => write!(stages, "
+/// The guest stage of test {id}.
+fn test_{id}_guest(_: &mut TestContext) -> StageFuture<'_> {{
+    Box::pin(async {{ {not_implemented} }})
+}}
+").unwrap(),
+                }
+            }
+            (None, Some(_)) => write!(stages, "
+/// The host stage of test {id}.
+fn test_{id}(ctx: &mut TestContext) -> StageFuture<'_> {{
+    Box::pin(async move {{
+        let addr = ctx.shared()?;
+        expect_access(ctx, &addr, Access::SigBus).await
+    }})
+}}
+").unwrap(),
+            (None, None) => write!(stages, "
+/// The host stage of test {id}.
+fn test_{id}(ctx: &mut TestContext) -> StageFuture<'_> {{
+    Box::pin(async move {{ module_result(ctx, {id}, \"ok\") }})
+}}
+").unwrap(),
+        }
+        stages
+    }
+
+    /// Writes `<name>.test.json` to the workspace, see [`crate::manifest::Manifest`]. It
+    /// describes the same test as the Rust code, which takes precedence over it, the stub is
+    /// where the test starts if it moves to a manifest. Returns `None` if a manifest cannot
+    /// describe the test.
+    pub fn write_manifest(&self) -> anyhow::Result<Option<PathBuf>> {
+        let path = self.manifest_path();
+        if path.exists() {
+            bail!("{} already exists", path.display());
+        }
+        let Some(manifest) = self.manifest() else {
+            info("No manifest stub written, a manifest test in a vmm reads shared memory, see --shared");
+            return Ok(None);
+        };
+        std::fs::write(&path, serde_json::to_string_pretty(&manifest)? + "\n")?;
+        info(&format!(
+            "Wrote the manifest stub of test {} ({}) to {}, it is ignored while the test is declared in Rust",
+            self.id,
+            self.name,
+            path.display()
+        ));
+        Ok(Some(path))
+    }
+
+    /// The manifest of the test, `None` for a test in a vmm without shared memory.
+    fn manifest(&self) -> Option<ManifestStub<'_>> {
+        let mut manifest = ManifestStub {
+            id: self.id,
+            name: &self.name,
+            description: "",
+            tags: Vec::new(),
+            modules: Vec::new(),
+            vmm: self.vmm,
+            shared: self.shared.as_deref(),
+            probe: "read",
+            expected: "sigbus",
+            requires: Vec::new(),
+        };
+        match (self.vmm, &self.shared) {
+            (Some(_), Some(_)) => {}
+            (None, Some(_)) => manifest.requires = vec!["root", "devmem"],
+            (None, None) => {
+                manifest.modules = vec![format!("test_{}", self.id)];
+                manifest.probe = "module-result";
+                manifest.expected = "ok";
+            }
+            (Some(_), None) => return None,
+        }
+        if self.realm_pa() {
+            manifest.modules = vec!["realm_pa_provider".to_string()];
+        }
+        Some(manifest)
+    }
+}
+
+/// The fields of a manifest in the order they are documented.
+#[derive(Serialize)]
+struct ManifestStub<'a> {
+    id: usize,
+    name: &'a str,
+    description: &'a str,
+    tags: Vec<&'a str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    modules: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    vmm: Option<QemuType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shared: Option<&'a str>,
+    probe: &'a str,
+    expected: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    requires: Vec<&'a str>,
+}
+
+/// The ids and names of the `TestCase::new(<id>, "<name>", ...)` entries in Rust source.
+fn declared_tests(src: &str) -> Vec<(usize, String)> {
+    src.split("TestCase::new(")
+        .skip(1)
+        .filter_map(|entry| {
+            let (id, rest) = entry.split_once(',')?;
+            let name = rest.trim_start().strip_prefix('"')?.split('"').next()?;
+            Some((id.trim().parse().ok()?, name.to_string()))
+        })
+        .collect()
+}
+
+fn info(message: &str) {
+    println!("{}", message.bright_red());
+}
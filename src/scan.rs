@@ -0,0 +1,103 @@
+use std::fmt::{Display, Formatter};
+use colored::Colorize;
+use log::info;
+use serde::{Deserialize, Serialize};
+use crate::probe::{hex, probe, FaultSignal, Op, ProbeOutcome, Width};
+
+/// What a host read of a page did, without the details that differ from page to page.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "result", rename_all = "kebab-case")]
+pub enum PageAccess {
+    Readable,
+    Sigbus {
+        code: String,
+    },
+    Sigsegv {
+        code: String,
+    },
+    MapFailed {
+        error: String,
+    },
+    Killed {
+        signal: i32,
+    },
+}
+
+impl From<ProbeOutcome> for PageAccess {
+    fn from(outcome: ProbeOutcome) -> Self {
+        match outcome {
+            ProbeOutcome::Readable { .. } | ProbeOutcome::Written { .. } => PageAccess::Readable,
+            ProbeOutcome::Fault(fault) => match fault.signal {
+                FaultSignal::SigBus => PageAccess::Sigbus { code: fault.code },
+                FaultSignal::SigSegv => PageAccess::Sigsegv { code: fault.code },
+            },
+            ProbeOutcome::MapFailed { error, .. } => PageAccess::MapFailed { error },
+            ProbeOutcome::Killed { signal } => PageAccess::Killed { signal },
+        }
+    }
+}
+
+impl Display for PageAccess {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PageAccess::Readable => write!(f, "readable"),
+            PageAccess::Sigbus { code } => write!(f, "SIGBUS ({code})"),
+            PageAccess::Sigsegv { code } => write!(f, "SIGSEGV ({code})"),
+            PageAccess::MapFailed { error } => write!(f, "mmap failed: {error}"),
+            PageAccess::Killed { signal } => write!(f, "killed by signal {signal}"),
+        }
+    }
+}
+
+/// Adjacent probed addresses that read the same way, `end` is exclusive.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ScanRange {
+    #[serde(with = "hex")]
+    pub start: u64,
+    #[serde(with = "hex")]
+    pub end: u64,
+    /// How many addresses were probed in the range.
+    pub probes: u64,
+    #[serde(flatten)]
+    pub access: PageAccess,
+}
+
+/// Reads every `step` bytes from `start` up to `end` in a worker each, and coalesces adjacent
+/// addresses that read the same way into ranges. A probe stands for the `step` bytes after it.
+pub fn scan(start: u64, end: u64, step: u64) -> anyhow::Result<Vec<ScanRange>> {
+    if end <= start {
+        anyhow::bail!("The end {end:#x} is not after the start {start:#x}");
+    }
+    if step == 0 || !step.is_multiple_of(Width::W64.bytes()) {
+        anyhow::bail!("The step {step:#x} is not a positive multiple of 8 bytes");
+    }
+    info!("Scanning {start:#x}..{end:#x} every {step:#x} bytes");
+    let mut ranges = Vec::<ScanRange>::new();
+    let mut addr = start;
+    while addr < end {
+        let access = PageAccess::from(probe(addr, Op::Read(Width::W64))?.outcome);
+        let next = addr.saturating_add(step).min(end);
+        match ranges.last_mut() {
+            Some(range) if range.access == access => {
+                range.end = next;
+                range.probes += 1;
+            }
+            _ => ranges.push(ScanRange { start: addr, end: next, probes: 1, access }),
+        }
+        addr = next;
+    }
+    Ok(ranges)
+}
+
+/// Prints the ranges of a scan as a table, one range per line.
+pub fn print_ranges(ranges: &[ScanRange]) {
+    println!("{}", format!("{:<18} {:<18} {:>8} result", "start", "end", "probes").bright_red());
+    for range in ranges {
+        let line = format!("{:<#18x} {:<#18x} {:>8} {}", range.start, range.end, range.probes, range.access);
+        match range.access {
+            PageAccess::Readable => println!("{}", line.green()),
+            PageAccess::MapFailed { .. } => println!("{}", line.yellow()),
+            _ => println!("{}", line.red()),
+        }
+    }
+}
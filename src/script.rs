@@ -4,10 +4,11 @@ use std::sync::OnceLock;
 use clap::Subcommand;
 use colored::Colorize;
 use walkdir::WalkDir;
+use crate::executor::executor_ref;
 
 static SCRIPTS: OnceLock<HashMap<String, String>> = OnceLock::new();
 
-fn script_ref() -> &'static HashMap<String, String> {
+pub fn script_ref() -> &'static HashMap<String, String> {
     SCRIPTS.get_or_init(|| {
         let mut scripts = HashMap::new();
         let dir = ".";
@@ -40,17 +41,16 @@ pub fn handle_script_command(sub: &ScriptSub) -> anyhow::Result<()> {
         ScriptSub::List => {
             scripts
                 .iter()
-                .for_each(|(name, path)| println!("{} {path}", format!("{name}").bright_red()));
+                .for_each(|(name, path)| println!("{} {path}", name.bright_red()));
         }
         ScriptSub::Exec { name } => {
             match scripts.get(name) {
                 Some(path) => {
-                    let mut cmd = Command::new("bash")
-                        .arg(path)
+                    let mut cmd = Command::new("bash");
+                    cmd.arg(path)
                         .stdin(Stdio::inherit())
-                        .stdout(Stdio::inherit())
-                        .spawn()?;
-                    cmd.wait()?;
+                        .stdout(Stdio::inherit());
+                    executor_ref().status(&mut cmd)?;
                 }
                 None => {
                     eprintln!("Failed to find script: {name}")
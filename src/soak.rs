@@ -0,0 +1,186 @@
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use colored::Colorize;
+use log::{info, warn};
+use serde::Serialize;
+use crate::executor::{executor_ref, record};
+use crate::qemu::manager_ref;
+use crate::report::TestResult;
+use crate::test::{run_attempt, Case, TestContext, TestOutcome};
+
+/// How `tt test soak` repeats a test, it stops after whichever limit is reached first.
+pub struct SoakOptions {
+    pub iterations: Option<usize>,
+    pub duration: Option<Duration>,
+    /// Whether every iteration gets a vmm of its own instead of reusing the one of the first.
+    pub recycle_vm: bool,
+    /// Overrides the timeout of the test.
+    pub timeout: Option<Duration>,
+    /// Where failing iterations and the statistics are saved.
+    pub logs: PathBuf,
+}
+
+/// Statistics of the iterations of a soaked test.
+#[derive(Serialize, Debug)]
+pub struct SoakStats {
+    pub id: usize,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    pub iterations: usize,
+    pub passed: usize,
+    /// Iterations that failed, errored or timed out.
+    pub failed: usize,
+    pub skipped: usize,
+    /// Passed iterations out of all iterations.
+    pub pass_rate: f64,
+    /// The first iteration that failed, counting from 1.
+    pub first_failure: Option<usize>,
+    /// How long launched vmms took to boot, over the iterations that launched one.
+    pub boot: Option<Percentiles>,
+    /// How long the test took without booting vmms.
+    pub test: Option<Percentiles>,
+}
+
+impl SoakStats {
+    pub fn print(&self) {
+        let mut label = format!("{} ({})", self.id, self.name);
+        if let Some(variant) = &self.variant {
+            write!(label, "[{variant}]").unwrap();
+        }
+        println!("{} {label}:", "Soak of test".bright_red());
+        println!(
+            "  {} iterations, {} passed, {} failed, {} skipped, pass rate {:.2}%",
+            self.iterations,
+            self.passed,
+            self.failed,
+            self.skipped,
+            self.pass_rate * 100.0,
+        );
+        match self.first_failure {
+            Some(iteration) => println!("  first failure in iteration {iteration}"),
+            None => println!("  no failure"),
+        }
+        for (what, percentiles) in [("boot", &self.boot), ("test", &self.test)] {
+            if let Some(p) = percentiles {
+                println!(
+                    "  {what} time: p50 {:.1}s, p90 {:.1}s, p99 {:.1}s, max {:.1}s",
+                    p.p50, p.p90, p.p99, p.max,
+                );
+            }
+        }
+    }
+}
+
+/// Percentiles of durations in seconds, by the nearest rank.
+#[derive(Serialize, Debug)]
+pub struct Percentiles {
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Percentiles {
+    fn of(mut durations: Vec<Duration>) -> Option<Self> {
+        if durations.is_empty() {
+            return None;
+        }
+        durations.sort();
+        let rank = |p: usize| durations[(p * durations.len()).div_ceil(100).max(1) - 1].as_secs_f64();
+        Some(Self {
+            p50: rank(50),
+            p90: rank(90),
+            p99: rank(99),
+            max: rank(100),
+        })
+    }
+}
+
+/// Runs the host stage of a case again and again, saving the results of failing iterations
+/// to `options.logs`. Ctrl-C stops after the statistics of the finished iterations are saved.
+///
+/// A dry run records a single iteration, and what it would save.
+pub async fn soak(case: Case, options: &SoakOptions) -> anyhow::Result<SoakStats> {
+    let dry_run = executor_ref().is_dry_run();
+    let context = if options.recycle_vm { TestContext::dedicated } else { TestContext::new };
+    // A duration too long to add to now never ends the soak.
+    let deadline = options.duration.and_then(|duration| Instant::now().checked_add(duration));
+    let mut stats = SoakStats {
+        id: case.test.id,
+        name: case.test.name.to_string(),
+        variant: case.variant.map(|variant| variant.name.to_string()),
+        iterations: 0,
+        passed: 0,
+        failed: 0,
+        skipped: 0,
+        pass_rate: 0.0,
+        first_failure: None,
+        boot: None,
+        test: None,
+    };
+    let (mut boots, mut tests) = (Vec::new(), Vec::new());
+
+    while options.iterations.is_none_or(|iterations| stats.iterations < iterations)
+        && deadline.is_none_or(|deadline| Instant::now() < deadline)
+    {
+        let iteration = stats.iterations + 1;
+        info!("{}", format!("Soak iteration {iteration} of test {}", case.label()).bright_red());
+        let ctx = context(case).with_timeout(options.timeout);
+        let results = tokio::select! {
+            results = run_attempt(ctx) => results,
+            _ = tokio::signal::ctrl_c() => {
+                warn!("Interrupted, iteration {iteration} is not counted");
+                break;
+            }
+        };
+        let result = &results[0];
+        stats.iterations = iteration;
+        match &result.outcome {
+            TestOutcome::Pass => stats.passed += 1,
+            TestOutcome::Skip(_) => stats.skipped += 1,
+            _ => {
+                stats.failed += 1;
+                stats.first_failure.get_or_insert(iteration);
+                if !dry_run {
+                    save_iteration(&options.logs, iteration, &results)?;
+                }
+            }
+        }
+        let boot = result.boot.unwrap_or_default();
+        if result.boot.is_some() {
+            boots.push(boot);
+        }
+        tests.push(result.duration.saturating_sub(boot));
+        if dry_run {
+            // Every other iteration would do the same.
+            break;
+        }
+    }
+    manager_ref().lock().unwrap().stop_all();
+
+    if stats.iterations > 0 {
+        stats.pass_rate = stats.passed as f64 / stats.iterations as f64;
+    }
+    stats.boot = Percentiles::of(boots);
+    stats.test = Percentiles::of(tests);
+    let path = options.logs.join("stats.json");
+    if dry_run {
+        record(&format!("write the statistics to {}", path.display()));
+        return Ok(stats);
+    }
+    std::fs::create_dir_all(&options.logs)?;
+    std::fs::write(&path, serde_json::to_string_pretty(&stats)?)?;
+    info!("Statistics written to {}", path.display());
+    Ok(stats)
+}
+
+/// Saves the results of a failing iteration with everything the test printed.
+fn save_iteration(logs: &Path, iteration: usize, results: &[TestResult]) -> anyhow::Result<()> {
+    std::fs::create_dir_all(logs)?;
+    let path = logs.join(format!("iteration-{iteration}.json"));
+    std::fs::write(&path, serde_json::to_string_pretty(results)?)?;
+    warn!("Iteration {iteration} {}, saved to {}", results[0].outcome, path.display());
+    Ok(())
+}
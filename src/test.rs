@@ -1,204 +1,1244 @@
-use log::info;
-use crate::client::{exec, upload};
-use crate::module::{install_module, realm_physical_address};
-use clap::Subcommand;
-use std::os::unix::prelude::ExitStatusExt;
-use std::process::{Command, Stdio};
-use crate::qemu::{manager_ref, shared_vmm_extra_args, start_confidential_vmm_if_no_exists, QemuType};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process::{ExitCode, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use anyhow::bail;
+use clap::{Subcommand, ValueEnum};
+use colored::Colorize;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use crate::binary::parse_addr;
+use crate::cases::registered;
+use crate::client::{exec, fetch, upload, ExecRes};
+use crate::executor::executor_ref;
+use crate::expect::{check_access, observe_access, Access};
+use crate::fixture::{Fixture, Fixtures, ModuleFixture, Shared, SharedMemory, VmmFixture};
+use crate::history;
+use crate::module::lock_module;
+use crate::manifest::{configure_manifests, manifests, Manifest};
+use crate::module_result::{discover_module_results, ModuleResult, Subtest, TEE_TESTS_DIR};
+use crate::probe::PAGE_SIZE;
+use crate::qemu::{manager_ref, QemuType, VmmResources};
+use crate::report::{Attempt, Report, ReportTarget, TestResult};
+use crate::requirement::Requirement;
+use crate::scaffold::NewTest;
+use crate::soak::{soak, SoakOptions};
+use crate::watch::watch;
 
-const DEFAULT_SHARED_ADDR: &str = "0000:00:03.0";
+pub const DEFAULT_SHARED_ADDR: &str = "0000:00:03.0";
+const DEFAULT_TIMEOUT: Duration = Duration::from_mins(10);
 
-#[derive(Subcommand, Clone, Debug)]
-pub enum TestSub {
-    Run { index: usize, args: Vec<String> },
+static TESTS: OnceLock<Vec<TestCase>> = OnceLock::new();
+
+/// All registered tests, in the order they were registered, followed by tests of manifests.
+pub fn tests_ref() -> &'static [TestCase] {
+    TESTS.get_or_init(|| {
+        let mut tests = registered();
+        for manifest in manifests() {
+            // Like the stub `tt test new` writes next to the Rust test it describes.
+            if tests.iter().any(|test| test.id == manifest.id && test.name == manifest.name) {
+                warn!(
+                    "Ignoring test manifest {}: test {} ({}) is declared in Rust too, remove one of them",
+                    manifest.path.display(),
+                    manifest.id,
+                    manifest.name
+                );
+                continue;
+            }
+            if tests.iter().any(|test| test.id == manifest.id || test.name == manifest.name) {
+                warn!(
+                    "Ignoring test manifest {}: test {} ({}) already exists",
+                    manifest.path.display(),
+                    manifest.id,
+                    manifest.name
+                );
+                continue;
+            }
+            tests.push(manifest.into_test());
+        }
+        tests
+    })
 }
 
-pub async fn handle_test_command(sub: &TestSub) -> anyhow::Result<()> {
-    match sub {
-        TestSub::Run { index, args } => {
-            match *index {
-                44 => test_44()?,
-                52 => test_52()?,
-                60 => test_60().await?,
-                601 => test_601()?,
-                82 => test_82().await?,
-                821 => test_821().await?,
-                83 => test_83().await?,
-                831 => test_831().await?,
-                _ => todo!(),
+/// The outcome of a test, an `Err` returned by a stage is reported as [`TestOutcome::Error`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", content = "reason", rename_all = "lowercase")]
+pub enum TestOutcome {
+    Pass,
+    Fail(String),
+    Skip(String),
+    Error(String),
+    /// The test did not finish in time, the reason is the timeout in seconds.
+    Timeout(u64),
+}
+
+impl TestOutcome {
+    pub fn fail(reason: impl Into<String>) -> Self {
+        TestOutcome::Fail(reason.into())
+    }
+
+    pub fn skip(reason: impl Into<String>) -> Self {
+        TestOutcome::Skip(reason.into())
+    }
+
+    /// Whether the test failed, could not run or did not finish.
+    pub fn is_broken(&self) -> bool {
+        matches!(self, TestOutcome::Fail(_) | TestOutcome::Error(_) | TestOutcome::Timeout(_))
+    }
+
+    /// The exit code of a run: 0 if nothing failed, 1 if a test failed and 2 if a test could not
+    /// run or did not finish.
+    pub fn exit_code<'a>(outcomes: impl IntoIterator<Item = &'a TestOutcome>) -> ExitCode {
+        let mut code = 0;
+        for outcome in outcomes {
+            match outcome {
+                TestOutcome::Fail(_) => code = code.max(1),
+                TestOutcome::Error(_) | TestOutcome::Timeout(_) => code = code.max(2),
+                TestOutcome::Pass | TestOutcome::Skip(_) => {}
             }
         }
+        ExitCode::from(code)
+    }
+}
+
+impl Display for TestOutcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TestOutcome::Pass => write!(f, "passed"),
+            TestOutcome::Fail(reason) => write!(f, "failed: {reason}"),
+            TestOutcome::Skip(reason) => write!(f, "skipped: {reason}"),
+            TestOutcome::Error(reason) => write!(f, "error: {reason}"),
+            TestOutcome::Timeout(secs) => write!(f, "timed out after {secs}s"),
+        }
     }
-    Ok(())
 }
 
-fn test_44() -> anyhow::Result<()> {
-    install_module("realm_pa_provider", &[])?;
+pub type StageFuture<'a> = Pin<Box<dyn Future<Output = anyhow::Result<TestOutcome>> + 'a>>;
+
+/// A stage of a test, it receives the context of the running test.
+pub type StageFn = for<'a> fn(&'a mut TestContext) -> StageFuture<'a>;
 
-    let addr = realm_physical_address()?;
-    let mut tt = Command::new("./tt")
-        .args(["binary", "read", &addr])
-        .spawn()?;
+/// A registered test.
+///
+/// The host stage is always executed by `tt test run` on the host OS, the
+/// optional guest stage is executed by the host stage inside a VMM through
+/// `tt test run <id> --stage guest`.
+pub struct TestCase {
+    pub id: usize,
+    pub name: &'static str,
+    pub description: &'static str,
+    pub tags: &'static [&'static str],
+    /// The type of VMM the test needs, `None` if it runs on the host only.
+    pub vmm: Option<QemuType>,
+    /// Kernel modules installed on the host before the host stage and removed after it.
+    pub modules: &'static [&'static str],
+    /// Scripts of the workspace the test runs, see `tt script list`.
+    pub scripts: &'static [&'static str],
+    /// The memory shared with the vmms of the test, set up after the modules.
+    pub shared: Option<Shared>,
+    /// What the environment must provide besides what the test implies, see
+    /// [`Case::requirements`].
+    pub requires: &'static [Requirement],
+    /// How long the host stage may take, including booting vmms.
+    pub timeout: Duration,
+    pub host: StageFn,
+    pub guest: Option<StageFn>,
+    /// The manifest the test was declared in, if it was not declared in Rust.
+    pub manifest: Option<Manifest>,
+    /// The configurations the test runs in, it runs once as declared if there are none.
+    pub variants: Vec<Variant>,
+}
 
-    // This child may be killed by a signal, so we cannot check its exit code.
-    let status = tt.wait()?;
-    if let Some(signal) = status.signal() {
-        const SIGBUS: i32 = 7;
-        if signal == SIGBUS {
-            println!("Test 44 passed: Process terminated by SIGBUS(7) as expected.");
-            return Ok(());
+impl TestCase {
+    pub fn new(id: usize, name: &'static str, host: StageFn) -> Self {
+        Self {
+            id,
+            name,
+            description: "",
+            tags: &[],
+            vmm: None,
+            modules: &[],
+            scripts: &[],
+            shared: None,
+            requires: &[],
+            timeout: DEFAULT_TIMEOUT,
+            host,
+            guest: None,
+            manifest: None,
+            variants: Vec::new(),
         }
-        eprintln!(
-            "Test 44 failed: Process terminated by an unexpected signal: {}",
-            signal
-        );
-        return Ok(());
     }
-    eprintln!(
-        "Test 44 failed: Process exited normally with code {:?} but expected a Bus error signal.",
-        status.code()
-    );
-    Ok(())
+
+    pub fn description(mut self, description: &'static str) -> Self {
+        self.description = description;
+        self
+    }
+
+    pub fn tags(mut self, tags: &'static [&'static str]) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    pub fn vmm(mut self, typ: QemuType) -> Self {
+        self.vmm = Some(typ);
+        self
+    }
+
+    pub fn modules(mut self, modules: &'static [&'static str]) -> Self {
+        self.modules = modules;
+        self
+    }
+
+    // No test runs a script yet.
+    #[allow(dead_code)]
+    pub fn scripts(mut self, scripts: &'static [&'static str]) -> Self {
+        self.scripts = scripts;
+        self
+    }
+
+    pub fn shared(mut self, shared: Shared) -> Self {
+        self.shared = Some(shared);
+        self
+    }
+
+    pub fn requires(mut self, requires: &'static [Requirement]) -> Self {
+        self.requires = requires;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn guest(mut self, guest: StageFn) -> Self {
+        self.guest = Some(guest);
+        self
+    }
+
+    pub fn manifest(mut self, manifest: Manifest) -> Self {
+        self.manifest = Some(manifest);
+        self
+    }
+
+    pub fn variants(mut self, variants: Vec<Variant>) -> Self {
+        self.variants = variants;
+        self
+    }
 }
 
-fn test_52() -> anyhow::Result<()> {
-    install_module("test_52", &[])?;
+/// A configuration a test runs in, a test with variants runs once per variant and each
+/// variant is reported as a case of its own.
+#[derive(Debug, Clone)]
+pub struct Variant {
+    pub name: &'static str,
+    /// Overrides the vmm type of the test.
+    pub vmm: Option<QemuType>,
+    /// Overrides the shared memory of the test.
+    pub shared: Option<Shared>,
+    pub smp: Option<u32>,
+    /// e.g. `1G` or `512M`.
+    pub memory: Option<&'static str>,
+    /// What reading the shared memory is expected to do in this variant, it takes precedence
+    /// over what the stages pass to [`crate::expect::expect_access`].
+    pub expected: Option<Access>,
+}
 
-    if std::fs::read_to_string("/proc/tee-tests/52/result")?.trim() == "ok" {
-        println!("Test 52 passed.");
-        return Ok(());
+impl Variant {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            vmm: None,
+            shared: None,
+            smp: None,
+            memory: None,
+            expected: None,
+        }
+    }
+
+    pub fn vmm(mut self, typ: QemuType) -> Self {
+        self.vmm = Some(typ);
+        self
+    }
+
+    pub fn shared(mut self, shared: Shared) -> Self {
+        self.shared = Some(shared);
+        self
+    }
+
+    pub fn smp(mut self, smp: u32) -> Self {
+        self.smp = Some(smp);
+        self
+    }
+
+    pub fn memory(mut self, memory: &'static str) -> Self {
+        self.memory = Some(memory);
+        self
+    }
+
+    pub fn expected(mut self, expected: Access) -> Self {
+        self.expected = Some(expected);
+        self
     }
-    eprintln!("Test 52 failed.");
-    Ok(())
 }
 
-/// The stage1 of test 60, which will be executed in host OS.
-async fn test_60() -> anyhow::Result<()> {
-    install_module("realm_pa_provider", &[])?;
-    let addr = realm_physical_address()?;
+/// A test, or one variant of a test with variants.
+#[derive(Clone, Copy)]
+pub struct Case {
+    pub test: &'static TestCase,
+    pub variant: Option<&'static Variant>,
+}
 
-    let mut manager = manager_ref().lock().unwrap();
-    let port = manager.spawn_auto_port(QemuType::Normal, Some(addr))?;
-    upload_tt(port).await?;
+impl Case {
+    /// The cases of `test`, one per variant if it has any.
+    pub fn all(test: &'static TestCase) -> Vec<Case> {
+        if test.variants.is_empty() {
+            return vec![Case { test, variant: None }];
+        }
+        test.variants.iter().map(|variant| Case { test, variant: Some(variant) }).collect()
+    }
 
-    let res = exec("/test/tt test run 601", port).await?;
-    if res.stdout.contains("Test 60 passed") {
-        println!("Test 60 passed. Process terminated by SIGBUS(7) as expected.");
-    } else {
-        println!("Test 60 failed.");
+    /// `<id> (<name>)`, followed by `[<variant>]` for a variant.
+    pub fn label(&self) -> String {
+        match self.variant {
+            Some(variant) => format!("{} ({})[{}]", self.test.id, self.test.name, variant.name),
+            None => format!("{} ({})", self.test.id, self.test.name),
+        }
+    }
+
+    pub fn vmm(&self) -> Option<QemuType> {
+        self.variant.and_then(|variant| variant.vmm).or(self.test.vmm)
+    }
+
+    pub fn shared(&self) -> Option<Shared> {
+        self.variant.and_then(|variant| variant.shared).or(self.test.shared)
+    }
+
+    pub fn resources(&self) -> VmmResources {
+        let mut resources = VmmResources::default();
+        if let Some(variant) = self.variant {
+            resources.smp = variant.smp.unwrap_or(resources.smp);
+            resources.memory = variant.memory.map(str::to_string).unwrap_or(resources.memory);
+        }
+        resources
+    }
+
+    /// The declared requirements followed by the ones implied by the modules, the vmm and the
+    /// shared memory of the case.
+    pub fn requirements(&self) -> Vec<Requirement> {
+        let test = self.test;
+        let mut requirements = test.requires.to_vec();
+        if !test.modules.is_empty() {
+            requirements.push(Requirement::Root);
+        }
+        requirements.extend(test.modules.iter().map(|module| Requirement::Module(module)));
+        if let Some(vmm) = self.vmm() {
+            requirements.extend([Requirement::Kvm, Requirement::Qemu]);
+            if vmm == QemuType::Confidential {
+                requirements.push(Requirement::Rme);
+            }
+            if self.shared().is_some() {
+                // The shared memory is mapped from /dev/mem by qemu.
+                requirements.extend([Requirement::Root, Requirement::DevMem]);
+            }
+        }
+        if self.shared() == Some(Shared::RealmPa) {
+            requirements.push(Requirement::RealmPaProvider);
+        }
+        let mut seen = Vec::new();
+        requirements.retain(|requirement| {
+            let first = !seen.contains(requirement);
+            seen.push(*requirement);
+            first
+        });
+        requirements
+    }
+
+    /// The command executed in the guest OS to run the guest stage.
+    pub fn guest_command(&self) -> String {
+        let test = self.test;
+        let mut command = format!("/test/tt test run {} --stage guest --result {}", test.id, self.guest_result_path());
+        if let Some(variant) = self.variant {
+            command.push_str(&format!(" --variant {}", variant.name));
+        }
+        if let Some(manifest) = &test.manifest {
+            command.push_str(&format!(" --manifest /test/{}", manifest.file_name()));
+        }
+        command
+    }
+
+    /// Where the guest stage writes its [`GuestResult`] in the guest OS.
+    pub fn guest_result_path(&self) -> String {
+        match self.variant {
+            Some(variant) => format!("/test/{}-{}.result.json", self.test.id, variant.name),
+            None => format!("/test/{}.result.json", self.test.id),
+        }
+    }
+
+    /// The line printed once a test finished.
+    pub fn summary(&self, outcome: &TestOutcome) -> String {
+        format!("Test {}: {outcome}", self.label())
+    }
+
+    pub fn print_summary(&self, outcome: &TestOutcome) {
+        let summary = self.summary(outcome);
+        match outcome {
+            TestOutcome::Pass => println!("{}", summary.green()),
+            TestOutcome::Fail(_) => println!("{}", summary.red()),
+            TestOutcome::Skip(_) => println!("{}", summary.yellow()),
+            TestOutcome::Error(_) | TestOutcome::Timeout(_) => println!("{}", summary.bright_red()),
+        }
+    }
+
+    /// Prints the output a test collected followed by its summary, all at once so that
+    /// tests running in parallel do not interleave.
+    pub fn print_result(&self, ctx: &TestContext, outcome: &TestOutcome) {
+        static PRINT: Mutex<()> = Mutex::new(());
+        let _print = PRINT.lock().unwrap();
+        print!("{}", ctx.stdout);
+        eprint!("{}", ctx.stderr);
+        print_subtests(&ctx.subtests);
+        self.print_summary(outcome);
     }
-    manager.stop(port);
-    Ok(())
 }
 
-/// The stage2 of test 60, which will be executed in guest OS.
-fn test_601() -> anyhow::Result<()> {
-    let pa = pa_from_shared(DEFAULT_SHARED_ADDR)?;
-    let mut tt = Command::new("/test/tt")
-        .args(["binary", "read", &pa])
-        .spawn()?;
-
-    // This child may be killed by a signal, so we cannot check its exit code.
-    let status = tt.wait()?;
-    if let Some(signal) = status.signal() {
-        const SIGBUS: i32 = 7;
-        if signal == SIGBUS {
-            println!("Test 60 passed: Process terminated by SIGBUS(7) as expected.");
-            return Ok(());
-        }
-        eprintln!(
-            "Test 60 failed: Process terminated by an unexpected signal: {}",
-            signal
-        );
-        return Ok(());
+fn print_subtests(subtests: &[Subtest]) {
+    for subtest in subtests {
+        let line = format!("  {}: {}", subtest.name, subtest.outcome);
+        match subtest.outcome {
+            TestOutcome::Pass => println!("{}", line.green()),
+            TestOutcome::Skip(_) => println!("{}", line.yellow()),
+            _ => println!("{}", line.red()),
+        }
+        for diagnostic in &subtest.diagnostics {
+            println!("    # {diagnostic}");
+        }
     }
-    eprintln!(
-        "Test 60 failed: Process exited normally with code {:?} but expected a Bus error signal.",
-        status.code()
-    );
-    Ok(())
 }
 
-// 0000:00:03:0
-fn pa_from_shared(pci: &str) -> anyhow::Result<String> {
-    info!("Finding shared pa.");
-    let lines = std::fs::read_to_string(format!("/sys/bus/pci/devices/{pci}/resource"))?;
-    let mut pa = "";
+/// The state of a running test, it collects everything the test printed.
+pub struct TestContext {
+    pub test: &'static TestCase,
+    /// The variant of the test that is running.
+    pub variant: Option<&'static Variant>,
+    pub stdout: String,
+    pub stderr: String,
+    /// The output of executing the guest stage, if the host stage executed it.
+    pub guest: Option<ExecRes>,
+    /// The result the guest stage reported.
+    pub guest_result: Option<GuestResult>,
+    /// The subtests of a kernel module test, each reported as a result of its own.
+    pub subtests: Vec<Subtest>,
+    /// How long the vmms the test launched took to boot, `None` if it launched none.
+    pub boot: Option<Duration>,
+    /// Whether the test gets vmms of its own instead of sharing them with other tests.
+    dedicated: bool,
+    /// What the host stage set up, torn down once it finished.
+    fixtures: Fixtures,
+    timeout: Duration,
+}
 
-    for line in lines.lines() {
-        let line = line.trim().split(" ").collect::<Vec<_>>();
-        let (start_addr, end_addr) = (
-            strip_radix16(line[0])?,
-            strip_radix16(line[1])?,
-        );
-        if end_addr - start_addr + 1 == 4096 {
-            pa = line[0];
+impl TestContext {
+    pub fn new(case: Case) -> Self {
+        let test = case.test;
+        Self {
+            test,
+            variant: case.variant,
+            stdout: String::new(),
+            stderr: String::new(),
+            guest: None,
+            guest_result: None,
+            subtests: Vec::new(),
+            boot: None,
+            dedicated: false,
+            fixtures: Fixtures::default(),
+            timeout: test.timeout,
+        }
+    }
+
+    /// A context whose vmms are spawned for this test only and stopped once it finished.
+    pub fn dedicated(case: Case) -> Self {
+        Self {
+            dedicated: true,
+            ..Self::new(case)
+        }
+    }
+
+    pub fn case(&self) -> Case {
+        Case {
+            test: self.test,
+            variant: self.variant,
+        }
+    }
+
+    /// What reading the shared memory is expected to do, `default` unless the variant says.
+    pub fn expected(&self, default: Access) -> Access {
+        self.variant.and_then(|variant| variant.expected).unwrap_or(default)
+    }
+
+    /// Runs one stage of the test, errors are turned into [`TestOutcome::Error`].
+    pub async fn run(&mut self, stage: Stage) -> TestOutcome {
+        let test = self.test;
+        let res = match stage {
+            Stage::Host => self.run_host().await,
+            Stage::Guest => match test.guest {
+                Some(guest) => guest(self).await,
+                None => Err(anyhow::anyhow!("Test {} has no guest stage", test.id)),
+            },
+        };
+        let outcome = res.unwrap_or_else(|e| TestOutcome::Error(format!("{e:#}")));
+        match outcome {
+            TestOutcome::Error(_) => outcome,
+            _ if executor_ref().is_dry_run() => TestOutcome::skip("dry run, nothing was executed"),
+            _ => outcome,
+        }
+    }
+
+    /// Overrides the timeout of the test.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        if let Some(timeout) = timeout {
+            self.timeout = timeout;
+        }
+        self
+    }
+
+    /// Runs the host stage between the setup and the teardown of the fixtures of the test.
+    async fn run_host(&mut self) -> anyhow::Result<TestOutcome> {
+        let res = self.run_host_with_fixtures().await;
+        self.fixtures.teardown();
+        res
+    }
+
+    async fn run_host_with_fixtures(&mut self) -> anyhow::Result<TestOutcome> {
+        let test = self.test;
+        let requirements = self.case().requirements();
+        if let Some(skip) = self.unmet(requirements.iter().filter(|r| !r.after_modules())) {
+            return Ok(skip);
+        }
+        self.setup_modules().await?;
+        if let Some(skip) = self.unmet(requirements.iter().filter(|r| r.after_modules())) {
+            return Ok(skip);
+        }
+        if let Some(shared) = self.case().shared() {
+            self.fixtures.push(Fixture::Shared(SharedMemory::setup(shared)?));
+        }
+
+        let timeout = self.timeout;
+        match tokio::time::timeout(timeout, (test.host)(self)).await {
+            Ok(res) => res,
+            Err(_) => {
+                // A vmm the test timed out on may hang, so it is not shared with later tests.
+                self.fixtures.vmms_mut().for_each(VmmFixture::own);
+                Ok(TestOutcome::Timeout(timeout.as_secs()))
+            }
+        }
+    }
+
+    async fn setup_modules(&mut self) -> anyhow::Result<()> {
+        let test = self.test;
+        let mut modules = test.modules.to_vec();
+        // Always lock in the same order, so that tests cannot deadlock each other.
+        modules.sort();
+        let mut locks = HashMap::new();
+        for module in modules {
+            locks.insert(module, lock_module(module).await);
+        }
+        for module in test.modules {
+            let Some(lock) = locks.remove(module) else {
+                continue;
+            };
+            self.fixtures.push(Fixture::Module(ModuleFixture::install(module, lock)?));
         }
+        Ok(())
     }
-    info!("{}", format!("Shared pa: {}", pa));
-    Ok(pa.to_string())
+
+    /// A skip naming every requirement in `requirements` the environment does not meet.
+    ///
+    /// A dry run only warns, it shows what would be executed where the requirements are met.
+    fn unmet<'a>(&self, requirements: impl Iterator<Item = &'a Requirement>) -> Option<TestOutcome> {
+        let reasons = requirements.filter_map(Requirement::unmet).collect::<Vec<_>>();
+        if reasons.is_empty() {
+            return None;
+        }
+        if executor_ref().is_dry_run() {
+            warn!("Test {} would be skipped: {}", self.case().label(), reasons.join("; "));
+            return None;
+        }
+        Some(TestOutcome::skip(reasons.join("; ")))
+    }
+
+    /// The address of the memory the test shares with its vmms.
+    pub fn shared(&self) -> anyhow::Result<String> {
+        match self.fixtures.shared() {
+            Some(addr) => Ok(addr.to_string()),
+            None => bail!("Test {} shares no memory", self.test.id),
+        }
+    }
+
+    pub fn push_output(&mut self, output: &Output) {
+        self.stdout.push_str(&String::from_utf8_lossy(&output.stdout));
+        self.stderr.push_str(&String::from_utf8_lossy(&output.stderr));
+    }
+
+    /// Finds or spawns a vmm of the type and resources the case needs, sharing the memory of
+    /// the test with the guest.
+    ///
+    /// Unless the context is dedicated, the vmm is shared with later tests needing the
+    /// same one and stopped at the end of the run, otherwise it is stopped with the test.
+    pub async fn vmm(&mut self) -> anyhow::Result<u16> {
+        let case = self.case();
+        let Some(typ) = case.vmm() else {
+            bail!("Test {} does not declare a vmm type", self.test.id);
+        };
+        let shared = self.fixtures.shared().map(str::to_string);
+        let resources = case.resources();
+        let (fixture, launched) = {
+            let mut manager = manager_ref().lock().unwrap();
+            if self.dedicated {
+                let port = manager.launch_auto_port(typ, shared.as_deref(), &resources)?;
+                let guard = manager.take(port).expect("A launched vmm is managed");
+                (VmmFixture::owned(port, guard), true)
+            } else {
+                let (port, launched) = manager.find_or_launch(typ, shared.as_deref(), &resources)?;
+                (VmmFixture::shared(port), launched)
+            }
+        };
+        let port = fixture.port;
+        self.fixtures.push(Fixture::Vmm(fixture));
+        if launched {
+            *self.boot.get_or_insert_default() += typ.wait_for_boot(port).await;
+        }
+        Ok(port)
+    }
+
+    /// Executes the guest stage in the guest OS listening at `port`, the outcome is the one
+    /// the guest stage reported.
+    pub async fn exec_guest(&mut self, port: u16) -> anyhow::Result<TestOutcome> {
+        let path = self.case().guest_result_path();
+        // Never read the result an earlier run left on a shared vmm.
+        exec(&format!("rm -f {path}"), port).await?;
+        self.guest = Some(exec(&self.case().guest_command(), port).await?);
+        if executor_ref().is_dry_run() {
+            return Ok(TestOutcome::skip("dry run, the guest stage reported no result"));
+        }
+
+        let content = fetch(&path, port)
+            .await
+            .map_err(|e| anyhow::anyhow!("The guest stage of test {} reported no result: {e}", self.test.id))?;
+        let result = serde_json::from_str::<GuestResult>(&content)?;
+        Ok(self.guest_result.insert(result).outcome.clone())
+    }
+}
+
+/// The result of a guest stage, written as json for the host stage.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GuestResult {
+    pub id: usize,
+    pub outcome: TestOutcome,
+    pub stdout: String,
+    pub stderr: String,
 }
 
-async fn test_82() -> anyhow::Result<()> {
-    // this address is usually using by kernel.
-    let target_addr = "0xFE940000";
+impl GuestResult {
+    pub fn new(ctx: &TestContext, outcome: &TestOutcome) -> Self {
+        Self {
+            id: ctx.test.id,
+            outcome: outcome.clone(),
+            stdout: ctx.stdout.clone(),
+            stderr: ctx.stderr.clone(),
+        }
+    }
+}
 
-    let mut manager = manager_ref().lock().unwrap();
-    let port = manager.spawn_auto_port(QemuType::Confidential, Some(target_addr))?;
-    upload_tt(port).await?;
+/// Finds a test by its id or its name.
+pub fn find_test(key: &str) -> anyhow::Result<&'static TestCase> {
+    let found = match key.parse::<usize>() {
+        Ok(id) => tests_ref().iter().find(|test| test.id == id),
+        Err(_) => tests_ref().iter().find(|test| test.name == key),
+    };
+    match found {
+        Some(test) => Ok(test),
+        None => bail!("Unknown test: {key}, see `tt test list`"),
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Host,
+    Guest,
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum TestSub {
+    /// list all registered tests.
+    List,
+    /// check how reading a physical address behaves, e.g. `tt test expect 0x80000000 sigbus`.
+    Expect {
+        addr: String,
+        /// readable, readable=<value>, sigbus, sigsegv or map-refused.
+        expected: Access,
+    },
+    /// report the results kernel module tests left in /proc/tee-tests, all of them by default.
+    ModuleResults {
+        ids: Vec<usize>,
+        /// the expected content of a result without subtests.
+        #[clap(long, default_value = "ok")]
+        expected: String,
+        /// write a report, `junit:<path>` or `json:<path>`, may be repeated.
+        #[clap(long)]
+        report: Vec<ReportTarget>,
+    },
+    /// scaffold a test: its stages and registry entry in Rust, and a manifest stub.
+    New {
+        /// the kebab-case name of the test.
+        name: String,
+        /// the id of the test, the next free one by default.
+        #[clap(long)]
+        id: Option<usize>,
+        /// the vmm the guest stage runs in, the test runs on the host only if absent.
+        #[clap(long)]
+        vmm: Option<QemuType>,
+        /// `realm-pa` or a physical address shared with the vmm.
+        #[clap(long)]
+        shared: Option<String>,
+        /// the Rust file the test is added to.
+        #[clap(long, default_value = "src/cases.rs")]
+        src: PathBuf,
+    },
+    /// run tests by their ids or names, one after another.
+    Run {
+        tests: Vec<String>,
+        /// run all registered tests.
+        #[clap(long)]
+        all: bool,
+        /// only run tests with one of these tags.
+        #[clap(long)]
+        tag: Vec<String>,
+        /// do not run these tests.
+        #[clap(long)]
+        exclude: Vec<String>,
+        /// run up to this many tests at the same time, each on its own vmm.
+        #[clap(long, short, default_value_t = 1)]
+        jobs: usize,
+        /// override the timeout of every test, e.g. `90s`, `5m` or `1h`.
+        #[clap(long, value_parser = parse_duration)]
+        timeout: Option<Duration>,
+        #[clap(long, value_enum, default_value_t = Stage::Host)]
+        stage: Stage,
+        /// write a report of the run, `junit:<path>` or `json:<path>`, may be repeated.
+        #[clap(long)]
+        report: Vec<ReportTarget>,
+        /// where the guest stage writes its result for the host stage.
+        #[clap(long)]
+        result: Option<PathBuf>,
+        /// load tests from these manifests too, the guest stage loads no other manifest.
+        #[clap(long)]
+        manifest: Vec<PathBuf>,
+        /// only run these variants of tests with variants.
+        #[clap(long)]
+        variant: Vec<String>,
+        /// run a failed test again up to this many times, it is flaky if it passes then.
+        #[clap(long, default_value_t = 0)]
+        retries: usize,
+        /// run the tests that failed in a json report.
+        #[clap(long, conflicts_with_all = ["tests", "all", "tag", "variant"])]
+        failed_from: Option<PathBuf>,
+    },
+    /// run tests, and again whenever a module, script or binary they may use changes.
+    Watch {
+        tests: Vec<String>,
+        /// watch all registered tests.
+        #[clap(long)]
+        all: bool,
+        /// only watch tests with one of these tags.
+        #[clap(long)]
+        tag: Vec<String>,
+        /// do not watch these tests.
+        #[clap(long)]
+        exclude: Vec<String>,
+        /// only run these variants of tests with variants.
+        #[clap(long)]
+        variant: Vec<String>,
+        /// override the timeout of every test, e.g. `90s`, `5m` or `1h`.
+        #[clap(long, value_parser = parse_duration)]
+        timeout: Option<Duration>,
+        /// how often files are checked for changes.
+        #[clap(long, value_parser = parse_duration, default_value = "1s")]
+        interval: Duration,
+        /// load tests from these manifests too.
+        #[clap(long)]
+        manifest: Vec<PathBuf>,
+    },
+    /// run a test again and again and report statistics, e.g. `tt test soak 82 --iterations 500`.
+    Soak {
+        test: String,
+        /// the variant to run of a test with variants.
+        #[clap(long)]
+        variant: Option<String>,
+        /// stop after this many iterations.
+        #[clap(long, required_unless_present = "duration")]
+        iterations: Option<usize>,
+        /// stop after this long, e.g. `8h`.
+        #[clap(long, value_parser = parse_duration)]
+        duration: Option<Duration>,
+        /// boot a fresh vmm for every iteration.
+        #[clap(long)]
+        recycle_vm: bool,
+        /// override the timeout of the test, e.g. `90s`, `5m` or `1h`.
+        #[clap(long, value_parser = parse_duration)]
+        timeout: Option<Duration>,
+        /// where failing iterations and the statistics are saved, `soak-<id>` by default.
+        #[clap(long)]
+        logs: Option<PathBuf>,
+        /// load tests from these manifests too.
+        #[clap(long)]
+        manifest: Vec<PathBuf>,
+    },
+}
 
-    let res = exec("/test/tt test run 821", port).await?;
-    if res.stdout.contains("passed") {
-        println!("Test 82 passed.");
+pub async fn handle_test_command(sub: &TestSub) -> anyhow::Result<ExitCode> {
+    match sub {
+        TestSub::List => {
+            for test in tests_ref() {
+                let vmm = test.vmm.map(|typ| format!("{typ:?}")).unwrap_or("-".to_string());
+                println!(
+                    "{} {} [{}] {vmm}: {}",
+                    test.id.to_string().bright_red(),
+                    test.name,
+                    test.tags.join(","),
+                    test.description,
+                );
+                let requirements = Case { test, variant: None }.requirements();
+                if !requirements.is_empty() {
+                    let requirements = requirements.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+                    println!("    requires {}", requirements.join(", "));
+                }
+                if !test.variants.is_empty() {
+                    let variants = test.variants.iter().map(|variant| variant.name).collect::<Vec<_>>();
+                    println!("    variants {}", variants.join(", "));
+                }
+            }
+        }
+        TestSub::Expect { addr, expected } => {
+            let (observed, output) = observe_access(addr).await?;
+            print!("{}", String::from_utf8_lossy(&output.stdout));
+            eprint!("{}", String::from_utf8_lossy(&output.stderr));
+            let outcome = check_access(addr, *expected, &observed);
+            println!("{outcome}");
+            return Ok(TestOutcome::exit_code([&outcome]));
+        }
+        TestSub::ModuleResults { ids, expected, report } => {
+            let ids = if ids.is_empty() { discover_module_results()? } else { ids.clone() };
+            if ids.is_empty() {
+                bail!("No kernel module test left a result in {TEE_TESTS_DIR}");
+            }
+            let mut results = Vec::new();
+            for id in ids {
+                let name = match tests_ref().iter().find(|test| test.id == id) {
+                    Some(test) => test.name.to_string(),
+                    None => format!("tee-tests-{id}"),
+                };
+                let (outcome, stdout, subtests) = match ModuleResult::read(id)? {
+                    Some(module) => (module.outcome(expected), module_diagnostics(&module), module.subtests),
+                    None => (module_result_missing(id), String::new(), Vec::new()),
+                };
+                print!("{stdout}");
+                print_subtests(&subtests);
+                println!("Test {id} ({name}): {outcome}");
+                let result = TestResult::module(id, name, outcome, stdout);
+                results.extend(result.with_subtests(&subtests));
+            }
+            print_run_summary(&results);
+            for target in report {
+                target.write(&results)?;
+            }
+            return Ok(TestOutcome::exit_code(results.iter().map(|result| &result.outcome)));
+        }
+        TestSub::New { name, id, vmm, shared, src } => {
+            NewTest::new(name, *id, *vmm, shared.as_deref(), src)?.write(src)?;
+        }
+        TestSub::Run {
+            tests,
+            all,
+            tag,
+            exclude,
+            jobs,
+            timeout,
+            stage,
+            report,
+            result,
+            manifest,
+            variant,
+            retries,
+            failed_from,
+        } => {
+            configure_manifests(manifest, *stage == Stage::Host);
+            let cases = match failed_from {
+                Some(path) => select_failed(path, exclude)?,
+                None => select_variants(select_tests(tests, *all, tag, exclude)?, variant)?,
+            };
+            if *stage == Stage::Guest {
+                let [case] = cases[..] else {
+                    bail!("The guest stage runs exactly one test, give `--variant` for a test with variants");
+                };
+                let mut ctx = TestContext::new(case);
+                let outcome = ctx.run(Stage::Guest).await;
+                case.print_result(&ctx, &outcome);
+                if let Some(path) = result {
+                    std::fs::write(path, serde_json::to_string(&GuestResult::new(&ctx, &outcome))?)?;
+                }
+                return Ok(TestOutcome::exit_code([&outcome]));
+            }
+
+            let options = RunOptions { jobs: *jobs, timeout: *timeout, retries: *retries };
+            let results = run_tests(cases, options).await?;
+            print_run_summary(&results);
+            if !executor_ref().is_dry_run()
+                && let Err(e) = history::record(&results)
+            {
+                warn!("Failed to record the run in {}: {e:#}", history::HISTORY_PATH);
+            }
+            for target in report {
+                target.write(&results)?;
+            }
+            return Ok(TestOutcome::exit_code(results.iter().map(|result| &result.outcome)));
+        }
+        TestSub::Watch { tests, all, tag, exclude, variant, timeout, interval, manifest } => {
+            configure_manifests(manifest, true);
+            let cases = select_variants(select_tests(tests, *all, tag, exclude)?, variant)?;
+            let options = RunOptions { jobs: 1, timeout: *timeout, retries: 0 };
+            watch(cases, options, *interval).await?;
+        }
+        TestSub::Soak { test, variant, iterations, duration, recycle_vm, timeout, logs, manifest } => {
+            configure_manifests(manifest, true);
+            let test = find_test(test)?;
+            let cases = select_variants(vec![test], variant.as_slice())?;
+            let [case] = cases[..] else {
+                bail!("Test {} has variants, give the one to soak with `--variant`", test.id);
+            };
+            let options = SoakOptions {
+                iterations: *iterations,
+                duration: *duration,
+                recycle_vm: *recycle_vm,
+                timeout: *timeout,
+                logs: logs.clone().unwrap_or(PathBuf::from(format!("soak-{}", test.id))),
+            };
+            let stats = soak(case, &options).await?;
+            stats.print();
+            if stats.failed > 0 {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Selects tests by ids or names, `--all` and tags, keeping the given order.
+fn select_tests(
+    keys: &[String],
+    all: bool,
+    tags: &[String],
+    exclude: &[String],
+) -> anyhow::Result<Vec<&'static TestCase>> {
+    let mut tests = if !keys.is_empty() {
+        keys.iter().map(|key| find_test(key)).collect::<anyhow::Result<Vec<_>>>()?
+    } else if all || !tags.is_empty() {
+        tests_ref().iter().collect()
     } else {
-        println!("Test 82 failed");
+        bail!("No test selected, give ids or names, `--tag` or `--all`");
+    };
+    if !tags.is_empty() {
+        tests.retain(|test| test.tags.iter().any(|tag| tags.iter().any(|t| t == tag)));
     }
-    manager.stop(port);
-    Ok(())
+    let excluded = exclude.iter().map(|key| find_test(key)).collect::<anyhow::Result<Vec<_>>>()?;
+    tests.retain(|test| !excluded.iter().any(|e| e.id == test.id));
+
+    let mut seen = Vec::new();
+    tests.retain(|test| {
+        let first = !seen.contains(&test.id);
+        seen.push(test.id);
+        first
+    });
+    if tests.is_empty() {
+        bail!("No test matches the selection");
+    }
+    Ok(tests)
 }
 
-async fn test_821() -> anyhow::Result<()> {
-    let pa = pa_from_shared(DEFAULT_SHARED_ADDR)?;
-    if read_mem_assert_signal_bus(&pa)? {
-        println!("passed");
-        return Ok(())
+/// Expands tests into their variants, keeping only the given variants of tests with variants.
+fn select_variants(tests: Vec<&'static TestCase>, variants: &[String]) -> anyhow::Result<Vec<Case>> {
+    for name in variants {
+        if !tests.iter().any(|test| test.variants.iter().any(|variant| variant.name == name)) {
+            bail!("No selected test has a variant {name}");
+        }
+    }
+    let mut cases = Vec::new();
+    for test in tests {
+        cases.extend(Case::all(test).into_iter().filter(|case| match case.variant {
+            Some(variant) => variants.is_empty() || variants.iter().any(|name| name == variant.name),
+            None => true,
+        }));
+    }
+    if cases.is_empty() {
+        bail!("No test matches the selection");
+    }
+    Ok(cases)
+}
+
+/// Selects the cases that failed, errored or timed out in a json report, keeping its order.
+fn select_failed(path: &Path, exclude: &[String]) -> anyhow::Result<Vec<Case>> {
+    let report = Report::load(path)?;
+    let excluded = exclude.iter().map(|key| find_test(key)).collect::<anyhow::Result<Vec<_>>>()?;
+    let mut cases = Vec::<Case>::new();
+    for result in report.results.iter().filter(|result| result.outcome.is_broken()) {
+        let Some(test) = tests_ref().iter().find(|test| test.id == result.id) else {
+            bail!("Test {} of {} does not exist, is its manifest missing?", result.label(), path.display());
+        };
+        if excluded.iter().any(|e| e.id == test.id) {
+            continue;
+        }
+        let failed = match &result.variant {
+            Some(name) => match test.variants.iter().find(|variant| variant.name == name) {
+                Some(variant) => vec![Case { test, variant: Some(variant) }],
+                None => bail!("Test {} has no variant {name} anymore", test.id),
+            },
+            None => Case::all(test),
+        };
+        for case in failed {
+            if !cases.iter().any(|c| c.label() == case.label()) {
+                cases.push(case);
+            }
+        }
+    }
+    if cases.is_empty() {
+        bail!("No test failed in {}", path.display());
+    }
+    Ok(cases)
+}
+
+/// Parses durations like `90`, `90s`, `5m`, `8h` or `1d`, a number alone is in seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (num, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let num = num.parse::<u64>().map_err(|_| format!("Invalid duration: `{s}`"))?;
+    let unit_secs = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 60 * 60 * 24,
+        _ => return Err(format!("Unknown unit of duration: `{unit}`, use s, m, h or d")),
+    };
+    let secs = num.checked_mul(unit_secs).ok_or_else(|| format!("Duration `{s}` is too long"))?;
+    Ok(Duration::from_secs(secs))
+}
+
+/// How `run_tests` runs tests.
+#[derive(Debug, Clone, Copy)]
+pub struct RunOptions {
+    pub jobs: usize,
+    /// Overrides the timeout of every test.
+    pub timeout: Option<Duration>,
+    /// How many times a failed test is run again.
+    pub retries: usize,
+}
+
+/// Runs the host stage of `cases`, results are in the order of `cases`.
+///
+/// With one job cases run one after another and share vmms needing the same one, otherwise
+/// up to `jobs` cases run at the same time, each on vmms of its own.
+pub async fn run_tests(cases: Vec<Case>, options: RunOptions) -> anyhow::Result<Vec<TestResult>> {
+    let results = if options.jobs <= 1 {
+        let mut results = Vec::new();
+        for case in cases {
+            results.extend(run_test(case, options, TestContext::new).await);
+        }
+        results
+    } else {
+        tokio::task::spawn_blocking(move || run_tests_parallel(&cases, options)).await??
+    };
+    manager_ref().lock().unwrap().stop_all();
+    Ok(results)
+}
+
+/// Runs tests on `jobs` threads, each with a runtime of its own since stages are not `Send`.
+fn run_tests_parallel(cases: &[Case], options: RunOptions) -> anyhow::Result<Vec<TestResult>> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(vec![None; cases.len()]);
+    std::thread::scope(|scope| -> anyhow::Result<()> {
+        let workers = (0..options.jobs.min(cases.len()))
+            .map(|_| {
+                scope.spawn(|| -> anyhow::Result<()> {
+                    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+                    loop {
+                        let index = next.fetch_add(1, Ordering::SeqCst);
+                        let Some(case) = cases.get(index) else {
+                            return Ok(());
+                        };
+                        let result = runtime.block_on(run_test(*case, options, TestContext::dedicated));
+                        results.lock().unwrap()[index] = Some(result);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for worker in workers {
+            worker.join().map_err(|_| anyhow::anyhow!("A test worker panicked"))??;
+        }
+        Ok(())
+    })?;
+    Ok(results.into_inner().unwrap().into_iter().flatten().flatten().collect())
+}
+
+/// Runs the host stage of a test in a fresh context, again while it fails and retries are left.
+///
+/// Its result holds the earlier attempts and is followed by the results of its subtests.
+async fn run_test(case: Case, options: RunOptions, context: fn(Case) -> TestContext) -> Vec<TestResult> {
+    let mut attempts = Vec::new();
+    loop {
+        let mut results = run_attempt(context(case).with_timeout(options.timeout)).await;
+        let outcome = &results[0].outcome;
+        if !outcome.is_broken() || attempts.len() >= options.retries {
+            results[0].attempts = attempts;
+            if results[0].is_flaky() {
+                warn!("Test {} is flaky, it passed on attempt {}", case.label(), results[0].attempts.len() + 1);
+            }
+            return results;
+        }
+        warn!("Test {} {outcome}, retrying ({}/{})", case.label(), attempts.len() + 1, options.retries);
+        attempts.push(Attempt::from(results.swap_remove(0)));
     }
-    println!("failed");
-    Ok(())
 }
 
-async fn test_83() -> anyhow::Result<()> {
-    todo!()
+/// Runs the host stage of a test once, its result is followed by the results of its subtests.
+pub async fn run_attempt(mut ctx: TestContext) -> Vec<TestResult> {
+    let case = ctx.case();
+    info!("{}", format!("Running test {}", case.label()).bright_red());
+    let start = Instant::now();
+    let outcome = ctx.run(Stage::Host).await;
+    let duration = start.elapsed();
+    case.print_result(&ctx, &outcome);
+    let subtests = std::mem::take(&mut ctx.subtests);
+    TestResult::new(ctx, outcome, duration).with_subtests(&subtests)
+}
+
+fn print_run_summary(results: &[TestResult]) {
+    if results.len() > 1 {
+        println!("{}", "Summary:".bright_red());
+        for result in results {
+            match result.attempts.len() {
+                0 => println!("  Test {}: {}", result.label(), result.outcome),
+                n if result.is_flaky() => {
+                    println!("  Test {}: {} (flaky, on attempt {})", result.label(), result.outcome, n + 1)
+                }
+                n => println!("  Test {}: {} ({} attempts)", result.label(), result.outcome, n + 1),
+            }
+        }
+    }
+    // A test with subtests is counted once, its subtests are counted on their own.
+    let (subtests, tests): (Vec<_>, Vec<_>) = results.iter().partition(|r| r.subtest.is_some());
+    let count = |results: &[&TestResult], f: fn(&TestOutcome) -> bool| results.iter().filter(|r| f(&r.outcome)).count();
+    println!(
+        "{} tests, {} passed, {} flaky, {} failed, {} skipped, {} errors, {} timed out",
+        tests.len(),
+        count(&tests, |o| matches!(o, TestOutcome::Pass)),
+        tests.iter().filter(|r| r.is_flaky()).count(),
+        count(&tests, |o| matches!(o, TestOutcome::Fail(_))),
+        count(&tests, |o| matches!(o, TestOutcome::Skip(_))),
+        count(&tests, |o| matches!(o, TestOutcome::Error(_))),
+        count(&tests, |o| matches!(o, TestOutcome::Timeout(_))),
+    );
+    if !subtests.is_empty() {
+        println!(
+            "{} subtests, {} passed, {} failed, {} skipped",
+            subtests.len(),
+            count(&subtests, |o| matches!(o, TestOutcome::Pass)),
+            count(&subtests, TestOutcome::is_broken),
+            count(&subtests, |o| matches!(o, TestOutcome::Skip(_))),
+        );
+    }
 }
 
-async fn test_831() -> anyhow::Result<()> {
-    todo!()
+/// Checks the result a kernel module test left in `/proc/tee-tests/<id>/result`, see
+/// [`ModuleResult`]. A legacy result without subtests is compared with `expected`.
+pub fn module_result(ctx: &mut TestContext, id: usize, expected: &str) -> anyhow::Result<TestOutcome> {
+    let Some(module) = ModuleResult::read(id)? else {
+        return Ok(module_result_missing(id));
+    };
+    let outcome = module.outcome(expected);
+    ctx.stdout.push_str(&module_diagnostics(&module));
+    ctx.subtests = module.subtests;
+    Ok(outcome)
 }
 
-fn strip_radix16(num: &str) -> anyhow::Result<u64> {
-    let striped = num.strip_prefix("0x").unwrap_or(num);
-    Ok(u64::from_str_radix(striped, 16)?)
+fn module_result_missing(id: usize) -> TestOutcome {
+    let path = ModuleResult::path(id);
+    TestOutcome::skip(format!("{} does not exist, is the module of test {id} loaded?", path.display()))
 }
 
-async fn upload_tt(port: u16) -> anyhow::Result<()> {
-    upload("./tt", port).await?;
+/// The diagnostic lines of a module which belong to none of its subtests.
+fn module_diagnostics(module: &ModuleResult) -> String {
+    module.diagnostics.iter().map(|line| format!("# {line}\n")).collect()
+}
+
+/// The physical address of the 4K resource of the ivshmem device at `pci`, like
+/// `0000:00:03.0`, in `0x` hex.
+pub fn pa_from_shared(pci: &str) -> anyhow::Result<String> {
+    info!("Finding shared pa.");
+    let lines = std::fs::read_to_string(format!("/sys/bus/pci/devices/{pci}/resource"))?;
+    let mut pa = None;
+
+    for line in lines.lines() {
+        let line = line.split_whitespace().collect::<Vec<_>>();
+        let [start, end, ..] = line[..] else {
+            continue;
+        };
+        let (start_addr, end_addr) = (parse_addr(start)?, parse_addr(end)?);
+        if end_addr >= start_addr && end_addr - start_addr + 1 == PAGE_SIZE {
+            pa = Some(start_addr);
+        }
+    }
+    let Some(pa) = pa else {
+        bail!("Device {pci} has no 4K resource to share");
+    };
+    info!("Shared pa: {pa:#x}");
+    Ok(format!("{pa:#x}"))
+}
+
+pub async fn upload_tt(ctx: &mut TestContext, port: u16) -> anyhow::Result<()> {
+    let res = upload("./tt", port).await?;
+    ctx.stdout.push_str(&res);
+    ctx.stdout.push('\n');
     exec("chmod +x /test/tt", port).await?;
     Ok(())
 }
 
-fn read_mem_assert_signal_bus(addr: &str) -> anyhow::Result<bool> {
-    let mut tt = Command::new("/test/tt")
-        .args(["binary", "read", addr])
-        .spawn()?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // This child may be killed by a signal, so we cannot check its exit code.
-    let status = tt.wait()?;
-    if let Some(signal) = status.signal() {
-        const SIGBUS: i32 = 7;
-        if signal == SIGBUS {
-            return Ok(true);
+    #[test]
+    fn parse_durations() {
+        let cases = [
+            ("90", 90),
+            ("90s", 90),
+            (" 5m ", 5 * 60),
+            ("8h", 8 * 60 * 60),
+            ("1d", 24 * 60 * 60),
+            ("0", 0),
+        ];
+        for (s, secs) in cases {
+            assert_eq!(parse_duration(s), Ok(Duration::from_secs(secs)), "{s}");
         }
-        return Ok(false);
     }
-    Ok(false)
-}
\ No newline at end of file
+
+    #[test]
+    fn reject_invalid_durations() {
+        for s in ["", "s", "-1", "1.5h", "1w", "5 m", "18446744073709551616", "18446744073709551615d"] {
+            assert!(parse_duration(s).is_err(), "{s}");
+        }
+    }
+}
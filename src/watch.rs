@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use colored::Colorize;
+use log::{info, warn};
+use crate::binary::binaries_ref;
+use crate::module::{install_module, lock_module, modules_ref, remove_module};
+use crate::qemu::manager_ref;
+use crate::report::TestResult;
+use crate::script::script_ref;
+use crate::test::{run_tests, Case, RunOptions, TestOutcome};
+
+/// A file of the workspace a watched test may depend on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Watched {
+    Module(String),
+    Script(String),
+    Binary(String),
+}
+
+impl Watched {
+    fn describe(&self) -> String {
+        match self {
+            Watched::Module(name) => format!("module {name}"),
+            Watched::Script(name) => format!("script {name}"),
+            Watched::Binary(name) => format!("binary {name}"),
+        }
+    }
+}
+
+/// Runs `cases` and runs them again whenever a module, script or binary of the workspace
+/// changes, until Ctrl-C.
+///
+/// A changed module or script reruns the cases using it, a changed binary reruns all cases
+/// since tests do not declare which binaries they run.
+pub async fn watch(cases: Vec<Case>, options: RunOptions, interval: Duration) -> anyhow::Result<()> {
+    let mut files = HashMap::new();
+    files.extend(modules_ref().iter().map(|(name, path)| (path.clone(), Watched::Module(name.clone()))));
+    files.extend(script_ref().iter().map(|(name, path)| (path.clone(), Watched::Script(name.clone()))));
+    // A script is found as a binary too if it is executable, it is watched as a script.
+    for (name, path) in binaries_ref() {
+        files.entry(path.clone()).or_insert(Watched::Binary(name.clone()));
+    }
+    let mut mtimes = files.keys().map(|path| (path.clone(), mtime(path))).collect::<HashMap<_, _>>();
+    info!("{}", format!("Watching {} files, Ctrl-C to stop", files.len()).bright_red());
+
+    // Ctrl-C also stops a cycle that is running, its vmms are stopped below.
+    let result = tokio::select! {
+        result = cycles(&cases, options, interval, &files, &mut mtimes) => result,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    };
+    manager_ref().lock().unwrap().stop_all();
+    result
+}
+
+/// Runs `cases` and runs the affected ones again on every change, until an error.
+async fn cycles(
+    cases: &[Case],
+    options: RunOptions,
+    interval: Duration,
+    files: &HashMap<String, Watched>,
+    mtimes: &mut HashMap<String, Option<SystemTime>>,
+) -> anyhow::Result<()> {
+    let mut cycle = 1;
+    print_cycle(cycle, "start", &run_tests(cases.to_vec(), options).await?);
+    loop {
+        tokio::time::sleep(interval).await;
+        let mut changed = changed_files(mtimes);
+        if changed.is_empty() {
+            continue;
+        }
+        // Wait for the build writing the files to finish.
+        loop {
+            tokio::time::sleep(interval).await;
+            let more = changed_files(mtimes);
+            if more.is_empty() {
+                break;
+            }
+            changed.extend(more);
+        }
+        changed.sort();
+        changed.dedup();
+
+        let changed = changed.iter().map(|path| &files[path]).collect::<Vec<_>>();
+        let affected = affected_cases(cases, &changed);
+        reload_modules(&changed, &affected).await;
+        cycle += 1;
+        let cause = changed.iter().map(|watched| watched.describe()).collect::<Vec<_>>().join(", ");
+        if affected.is_empty() {
+            println!("{}", format!("[{cycle}] {cause} changed, no watched test depends on it").yellow());
+            continue;
+        }
+        print_cycle(cycle, &format!("{cause} changed"), &run_tests(affected, options).await?);
+    }
+}
+
+fn mtime(path: &str) -> Option<SystemTime> {
+    Path::new(path).metadata().and_then(|meta| meta.modified()).ok()
+}
+
+/// The files whose modification time changed since the last call, their new time is kept.
+fn changed_files(mtimes: &mut HashMap<String, Option<SystemTime>>) -> Vec<String> {
+    let mut changed = Vec::new();
+    for (path, last) in mtimes.iter_mut() {
+        let now = mtime(path);
+        if now != *last {
+            *last = now;
+            changed.push(path.clone());
+        }
+    }
+    changed
+}
+
+fn affected_cases(cases: &[Case], changed: &[&Watched]) -> Vec<Case> {
+    cases
+        .iter()
+        .filter(|case| {
+            changed.iter().any(|watched| match watched {
+                Watched::Module(name) => case.test.modules.contains(&name.as_str()),
+                Watched::Script(name) => case.test.scripts.contains(&name.as_str()),
+                Watched::Binary(_) => true,
+            })
+        })
+        .copied()
+        .collect()
+}
+
+/// Removes the old build of changed modules that are loaded. The fixtures of the affected
+/// tests install the new build, a module no affected test uses is installed again right away.
+async fn reload_modules(changed: &[&Watched], affected: &[Case]) {
+    for watched in changed {
+        let Watched::Module(name) = watched else {
+            continue;
+        };
+        if !Path::new("/sys/module").join(name.replace('-', "_")).exists() {
+            continue;
+        }
+        let _lock = lock_module(name).await;
+        info!("Reloading module {name}");
+        if let Err(e) = remove_module(name) {
+            warn!("{e:#}");
+            continue;
+        }
+        let used = affected.iter().any(|case| case.test.modules.contains(&name.as_str()));
+        if !used && let Err(e) = install_module(name, &[]) {
+            warn!("{e:#}");
+        }
+    }
+}
+
+/// Prints one line for a cycle of the watch.
+fn print_cycle(cycle: usize, cause: &str, results: &[TestResult]) {
+    let results = results.iter().filter(|result| result.subtest.is_none()).collect::<Vec<_>>();
+    let count = |f: fn(&TestOutcome) -> bool| results.iter().filter(|r| f(&r.outcome)).count();
+    let passed = count(|o| *o == TestOutcome::Pass);
+    let broken = count(TestOutcome::is_broken);
+    let mut line = format!("[{cycle}] {cause}: {} tests, {passed} passed, {broken} failed", results.len());
+    let failed = results.iter().filter(|r| r.outcome.is_broken()).map(|r| r.label()).collect::<Vec<_>>();
+    if !failed.is_empty() {
+        write!(line, " ({})", failed.join(", ")).unwrap();
+    }
+    if broken > 0 {
+        println!("{}", line.red());
+    } else {
+        println!("{}", line.green());
+    }
+}